@@ -0,0 +1,210 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, info};
+
+/// How many generations are kept, older ones are removed when a new one is saved.
+pub const GENERATIONS_KEPT: usize = 20;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("There are no backups")]
+    NoBackups,
+    #[error("Backup generation {} does not exist", .0)]
+    UnknownGeneration(u32),
+    #[error("Failed to parse backup manifest")]
+    ParseError(#[from] serde_json::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// The contents of files before a single command changed them.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Generation {
+    pub id: u32,
+    /// When the backup was made, in seconds since the unix epoch.
+    pub created_at: u64,
+    /// The command that changed the files.
+    pub command: String,
+    pub files: Vec<BackedUpFile>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BackedUpFile {
+    pub dest: PathBuf,
+    /// Name of the copy of the file in the generation directory.
+    /// `None` if the file did not exist.
+    pub backup: Option<String>,
+}
+
+impl Generation {
+    /// Start a new generation, after all the existing ones.
+    pub fn new(command: impl Into<String>) -> Result<Generation, Error> {
+        let id = Self::ids()?.last().map(|id| id + 1).unwrap_or(1);
+        Ok(Generation {
+            id,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            command: command.into(),
+            files: vec![],
+        })
+    }
+
+    fn root() -> PathBuf {
+        crate::state_dir().join("backups")
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        Self::root().join(self.id.to_string())
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created_at)
+    }
+
+    /// Ids of all the generations, oldest first.
+    fn ids() -> Result<Vec<u32>, Error> {
+        let root = Self::root();
+        if !root.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut ids = root
+            .read_dir()?
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+            .collect::<Vec<_>>();
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn load(id: u32) -> Result<Generation, Error> {
+        let manifest = Self::root().join(id.to_string()).join("manifest.json");
+        if !manifest.exists() {
+            return Err(Error::UnknownGeneration(id));
+        }
+        let text = std::fs::read_to_string(manifest)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// All the generations, oldest first.
+    pub fn list() -> Result<Vec<Generation>, Error> {
+        Self::ids()?
+            .into_iter()
+            .map(Self::load)
+            .filter(|generation| !matches!(generation, Err(Error::UnknownGeneration(_))))
+            .collect()
+    }
+
+    /// The most recent generation made by `command`.
+    pub fn latest(command: &str) -> Result<Generation, Error> {
+        Self::list()?
+            .into_iter()
+            .rev()
+            .find(|generation| generation.command == command)
+            .ok_or(Error::NoBackups)
+    }
+
+    /// Save the current contents of `dest`, before it is changed.
+    pub fn save(&mut self, dest: &Path) -> Result<(), Error> {
+        if self.files.iter().any(|file| file.dest == dest) {
+            return Ok(());
+        }
+
+        let backup = if dest.is_file() {
+            let dir = self.dir();
+            crate::create_dir_all_if_not_exists!(&dir)?;
+            let name = self.files.len().to_string();
+            let copy = dir.join(&name);
+            crate::copy_file!(dest, &copy)?;
+            Some(name)
+        } else {
+            None
+        };
+
+        self.files.push(BackedUpFile {
+            dest: dest.to_path_buf(),
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Write the manifest, if any files were saved.
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+
+        let dir = self.dir();
+        crate::create_dir_all_if_not_exists!(&dir)?;
+        std::fs::write(
+            dir.join("manifest.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        info!(
+            "Backed up {} files to generation {}",
+            self.files.len(),
+            self.id
+        );
+
+        Self::prune(GENERATIONS_KEPT)?;
+        Ok(())
+    }
+
+    /// Remove all but the `keep` most recent generations. Returns the ids that were removed.
+    pub fn prune(keep: usize) -> Result<Vec<u32>, Error> {
+        let ids = Self::ids()?;
+        let old = &ids[..ids.len().saturating_sub(keep)];
+        for id in old {
+            std::fs::remove_dir_all(Self::root().join(id.to_string()))?;
+            debug!("Removed backup generation {id}");
+        }
+        Ok(old.to_vec())
+    }
+
+    /// Put files back how they were before this generation.
+    ///
+    /// Only restores files under `paths`, unless it is empty.
+    /// Files that did not exist are removed again.
+    pub fn restore(
+        &self,
+        paths: &[PathBuf],
+        backup: &mut Generation,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let mut restored = vec![];
+        for file in &self.files {
+            if !paths.is_empty() && !paths.iter().any(|path| file.dest.starts_with(path)) {
+                continue;
+            }
+
+            backup.save(&file.dest)?;
+            match &file.backup {
+                Some(name) => {
+                    if let Some(parent) = file.dest.parent() {
+                        crate::create_dir_all_if_not_exists!(parent)?;
+                    }
+                    // The file might be a link into the repository, which must not be written through.
+                    if file.dest.exists() || file.dest.is_symlink() {
+                        std::fs::remove_file(&file.dest)?;
+                    }
+                    let copy = self.dir().join(name);
+                    crate::copy_file!(&copy, &file.dest)?;
+                }
+                None => {
+                    if file.dest.exists() || file.dest.is_symlink() {
+                        std::fs::remove_file(&file.dest)?;
+                    }
+                }
+            }
+            debug!("Restored '{}'", file.dest.display());
+            restored.push(file.dest.clone());
+        }
+        Ok(restored)
+    }
+}
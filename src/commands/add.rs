@@ -1,17 +1,24 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
 use color_eyre::Result;
 use color_eyre::{eyre::eyre, Section};
+use ignore::gitignore::Gitignore;
 use tracing::{debug, warn};
 
-use crate::{namespace::determine_namespace, repository::RepositoryBuilder};
+use crate::{
+    commands::commit::auto_commit, namespace::determine_namespace, repository::RepositoryBuilder,
+    secret,
+};
 
 #[derive(Parser, Debug)]
 pub struct AddOptions {
     files: Vec<PathBuf>,
     #[clap(long)]
     mock: bool,
+    /// Encrypt the files with age, so they are stored as `.age` files in the repository
+    #[clap(long)]
+    encrypt: bool,
 }
 
 pub fn add(repo_builder: RepositoryBuilder, options: &AddOptions) -> Result<()> {
@@ -19,6 +26,8 @@ pub fn add(repo_builder: RepositoryBuilder, options: &AddOptions) -> Result<()>
 
     let mut io_errors: Vec<std::io::Error> = vec![];
     let mut prefix_errors: Vec<std::path::StripPrefixError> = vec![];
+    let mut copy_errors: Vec<secret::Error> = vec![];
+    let mut added: Vec<PathBuf> = vec![];
     for file in &options.files {
         debug!("Adding file '{}'", file.display());
 
@@ -33,9 +42,9 @@ pub fn add(repo_builder: RepositoryBuilder, options: &AddOptions) -> Result<()>
 
         let new_path = namespace.location.join(
             match file.strip_prefix(
-                &namespace
+                namespace
                     .targets
-                    .get(0)
+                    .first()
                     .expect("This is impossible. The logic in determine_namespace must be wrong"),
             ) {
                 Ok(path) => path,
@@ -58,19 +67,36 @@ pub fn add(repo_builder: RepositoryBuilder, options: &AddOptions) -> Result<()>
         }
 
         let output_path = &namespace.location.join(new_path);
+        let ignore = namespace.ignore_matcher()?;
+        if ignore
+            .matched_path_or_any_parents(output_path, file.is_dir())
+            .is_ignore()
+        {
+            println!("Not adding '{}', it is ignored", file.display());
+            continue;
+        }
+
         if options.mock {
             println!("{} -> {}", file.display(), output_path.display());
-        } else if file.is_file() {
-            match crate::copy_file!(&file, output_path) {
-                Ok(_) => {}
-                Err(e) => io_errors.push(e),
-            }
-        } else if file.is_dir() {
-            crate::copy_dir!(&file, output_path);
+        } else if let Err(e) = copy(
+            repository.path(),
+            &ignore,
+            &file,
+            output_path,
+            options.encrypt,
+        ) {
+            copy_errors.push(e);
+        } else if options.encrypt && !file.is_dir() {
+            added.push(encrypted_path(output_path));
+        } else {
+            added.push(output_path.clone());
         }
     }
 
-    let total_errors = io_errors.len() + prefix_errors.len();
+    // Commit what was added, even if some files failed.
+    auto_commit(&repository, "Add", &added)?;
+
+    let total_errors = io_errors.len() + prefix_errors.len() + copy_errors.len();
     match total_errors {
         0 => Ok(()),
         _ => {
@@ -85,7 +111,53 @@ pub fn add(repo_builder: RepositoryBuilder, options: &AddOptions) -> Result<()>
             for err in prefix_errors {
                 error = error.with_error(|| err);
             }
+            for err in copy_errors {
+                error = error.with_error(|| err);
+            }
             Err(error)
         }
     }
 }
+
+/// Copy `file` into `output_path`, skipping ignored files in directories.
+/// Encrypted files have `.age` added to the end of their name.
+fn copy(
+    repository: &Path,
+    ignore: &Gitignore,
+    file: &Path,
+    output_path: &Path,
+    encrypt: bool,
+) -> Result<(), secret::Error> {
+    if file.is_dir() {
+        crate::create_dir_all_if_not_exists!(output_path)?;
+        for entry in crate::read_dir!(file)? {
+            let entry = entry?;
+            let output_path = output_path.join(entry.file_name());
+            if ignore
+                .matched(&output_path, entry.file_type()?.is_dir())
+                .is_ignore()
+            {
+                debug!("Ignoring '{}'", entry.path().display());
+                continue;
+            }
+            copy(repository, ignore, &entry.path(), &output_path, encrypt)?;
+        }
+        return Ok(());
+    }
+
+    if !encrypt {
+        crate::copy_file!(file, output_path)?;
+        return Ok(());
+    }
+
+    let ciphertext = secret::encrypt(repository, &std::fs::read(file)?)?;
+    std::fs::write(encrypted_path(output_path), ciphertext)?;
+    Ok(())
+}
+
+fn encrypted_path(path: &Path) -> PathBuf {
+    let mut encrypted_path = path.as_os_str().to_owned();
+    encrypted_path.push(".");
+    encrypted_path.push(secret::EXTENSION);
+    PathBuf::from(encrypted_path)
+}
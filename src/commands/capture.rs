@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use tracing::{debug, info};
+
+use crate::{
+    commands::commit::auto_commit,
+    deployment::{self, Deployment},
+    namespace::determine_namespace,
+    repository::RepositoryBuilder,
+    state::{self, State},
+};
+
+#[derive(Debug, Args)]
+pub struct CaptureOptions {
+    /// Only capture these files
+    paths: Vec<PathBuf>,
+    /// Print what would be captured, without changing any files.
+    #[clap(long)]
+    dry_run: bool,
+    /// Capture files even if they were also changed in the repository.
+    #[clap(short, long)]
+    force: bool,
+}
+
+pub fn capture(repo_builder: RepositoryBuilder, options: &CaptureOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+
+    let paths = options
+        .paths
+        .iter()
+        .map(|path| crate::absolute_path(path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    // Only look at the namespaces the paths belong to.
+    let mut namespaces = repository.namespaces()?;
+    if !paths.is_empty() {
+        let mut names = vec![];
+        for path in &paths {
+            names.push(determine_namespace(&repository, path)?.name().to_string());
+        }
+        namespaces.retain(|ns| names.iter().any(|name| name == ns.name()));
+    }
+
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+    let mut state = State::load(repository.path())?;
+
+    let mut captured: Vec<PathBuf> = vec![];
+    for deployment in deployment::collect(&namespaces, &plugin_map, &vars, None)? {
+        if !paths.is_empty() && !paths.iter().any(|path| deployment.dest.starts_with(path)) {
+            continue;
+        }
+        if !deployment.dest.is_file() {
+            continue;
+        }
+
+        let contents = std::fs::read(&deployment.dest)?;
+        let recorded = state.as_ref().and_then(|s| s.get(&deployment.dest));
+
+        if !deployment.plugins.is_empty() {
+            // The plugins can't be run backwards, so just let the user know about the edit.
+            if recorded.is_none_or(|file| file.hash != state::hash(&contents)) {
+                eprintln!(
+                    "Not capturing '{}': it is produced by plugins, edit '{}' instead",
+                    deployment.dest.display(),
+                    deployment.source.display()
+                );
+            }
+            continue;
+        }
+
+        let source = std::fs::read(&deployment.source)?;
+        if source == contents {
+            continue;
+        }
+
+        if captured.contains(&deployment.source) {
+            eprintln!(
+                "Not capturing '{}': '{}' was already captured from another target",
+                deployment.dest.display(),
+                deployment.source.display()
+            );
+            continue;
+        }
+
+        if !options.force && changed_in_repository(&deployment, &source, recorded)? {
+            eprintln!(
+                "Not capturing '{}': '{}' was also changed in the repository, use --force to overwrite it",
+                deployment.dest.display(),
+                deployment.source.display()
+            );
+            continue;
+        }
+
+        println!(
+            "capture    {} -> {}",
+            deployment.dest.display(),
+            deployment.source.display()
+        );
+        if options.dry_run {
+            continue;
+        }
+
+        crate::copy_file!(&deployment.dest, &deployment.source).wrap_err(format!(
+            "Failed to copy '{}' into the repository",
+            deployment.dest.display()
+        ))?;
+        captured.push(deployment.source.clone());
+
+        // The system and repository agree again.
+        if let Some(file) = state
+            .as_mut()
+            .and_then(|s| s.files.iter_mut().find(|f| f.dest == deployment.dest))
+        {
+            file.hash = state::hash(&contents);
+            file.source_hash = state::hash(&contents);
+        }
+    }
+
+    if let Some(state) = &state {
+        if !captured.is_empty() {
+            state.save().context("Failed to save deployment state")?;
+        }
+    }
+
+    info!("Captured {} files", captured.len());
+    auto_commit(&repository, "Capture", &captured)?;
+
+    Ok(())
+}
+
+/// Whether the file in the repository changed since it was last deployed.
+fn changed_in_repository(
+    deployment: &Deployment,
+    source: &[u8],
+    recorded: Option<&state::DeployedFile>,
+) -> Result<bool> {
+    if let Some(file) = recorded {
+        return Ok(file.source_hash != state::hash(source));
+    }
+
+    // Without a record of the last deploy, fall back to whichever was edited last.
+    let source_modified = std::fs::metadata(&deployment.source)?.modified()?;
+    let dest_modified = std::fs::metadata(&deployment.dest)?.modified()?;
+    debug!(
+        ?source_modified,
+        ?dest_modified,
+        "No deployment record for '{}'",
+        deployment.dest.display()
+    );
+    Ok(source_modified > dest_modified)
+}
@@ -8,6 +8,9 @@ use crate::repository::RepositoryBuilder;
 #[derive(Debug, Args)]
 pub struct CloneOptions {
     url: Url,
+    /// Ask where to deploy each namespace that could not be set up automatically
+    #[clap(short, long)]
+    interactive: bool,
 }
 
 pub fn clone(repo_builder: RepositoryBuilder, options: &CloneOptions) -> Result<()> {
@@ -16,12 +19,16 @@ pub fn clone(repo_builder: RepositoryBuilder, options: &CloneOptions) -> Result<
     info!("Repository cloned successfully");
 
     // Any user-made namespaces must be added manually.
+    if options.interactive {
+        return crate::commands::namespace::set_floating(&repository);
+    }
+
     let floating_namespaces = repository.floating_namespaces()?;
     if !floating_namespaces.is_empty() {
         println!();
         println!("The following namespaces could not be auto-generated, and must be set manually.");
-        println!("To do this, run `fig namespace set <namespace> <path>`");
-        for ns in repository.floating_namespaces()? {
+        println!("To do this, run `fig namespace set <namespace> <path>`, or `fig namespace set` to set each of them in turn");
+        for ns in floating_namespaces {
             println!("\t{ns}");
         }
     }
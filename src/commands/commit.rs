@@ -0,0 +1,123 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+
+use crate::repository::{Repository, RepositoryBuilder};
+
+#[derive(Debug, Args)]
+pub struct CommitOptions {
+    /// The commit message
+    #[clap(short, long)]
+    message: String,
+}
+
+pub fn commit(repo_builder: RepositoryBuilder, options: &CommitOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+
+    match repository
+        .commit(&options.message)
+        .context("Failed to commit changes")?
+    {
+        Some(oid) => println!("Committed {}: {}", short(oid), options.message),
+        None => println!("Nothing to commit"),
+    }
+
+    Ok(())
+}
+
+/// The abbreviated form of a commit id, as git shows it.
+pub fn short(oid: git2::Oid) -> String {
+    oid.to_string()[..7].to_string()
+}
+
+/// Commit what a command changed in `paths`, if `auto_commit` is set in settings.toml.
+/// `action` starts the message, which lists the files and namespaces that changed.
+pub fn auto_commit(repository: &Repository, action: &str, paths: &[PathBuf]) -> Result<()> {
+    if paths.is_empty() || !repository.load_settings()?.auto_commit {
+        return Ok(());
+    }
+
+    let mut subject = String::new();
+    let oid = repository
+        .commit_paths(paths, |files| {
+            let message = message(action, files);
+            subject = message.lines().next().unwrap_or_default().to_string();
+            message
+        })
+        .context("Failed to commit changes")?;
+    if let Some(oid) = oid {
+        println!("Committed {}: {subject}", short(oid));
+    }
+
+    Ok(())
+}
+
+/// A message like `Add: 2 files in home`, followed by the namespaces and each of the files.
+fn message(action: &str, files: &[PathBuf]) -> String {
+    // Files in the root of the repository, such as plugins.toml, are not in a namespace.
+    let namespaces = files
+        .iter()
+        .filter(|file| file.components().count() > 1)
+        .filter_map(|file| file.components().next())
+        .map(|component| Path::new(component.as_os_str()).display().to_string())
+        .collect::<BTreeSet<_>>();
+    let namespaces = namespaces.into_iter().collect::<Vec<_>>().join(", ");
+
+    let mut message = match files {
+        [file] => format!("{action}: {}", file.display()),
+        _ if namespaces.is_empty() => format!("{action}: {} files", files.len()),
+        _ => format!("{action}: {} files in {namespaces}", files.len()),
+    };
+    message.push('\n');
+    if !namespaces.is_empty() {
+        message.push_str(&format!("\nNamespaces: {namespaces}\n"));
+    }
+    message.push_str("\nFiles:\n");
+    for file in files {
+        message.push_str(&format!("  {}\n", file.display()));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(files: &[&str]) -> Vec<PathBuf> {
+        files.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn message_one_file() {
+        assert_eq!(
+            message("Add", &paths(&["home/.bashrc"])),
+            "Add: home/.bashrc\n\nNamespaces: home\n\nFiles:\n  home/.bashrc\n"
+        );
+    }
+
+    #[test]
+    fn message_root_file() {
+        assert_eq!(
+            message("Update", &paths(&["plugins.toml"])),
+            "Update: plugins.toml\n\nFiles:\n  plugins.toml\n"
+        );
+        assert_eq!(
+            message("Update", &paths(&["plugins.toml", "vars.toml"])),
+            "Update: 2 files\n\nFiles:\n  plugins.toml\n  vars.toml\n"
+        );
+    }
+
+    #[test]
+    fn message_several_namespaces() {
+        assert_eq!(
+            message(
+                "Add",
+                &paths(&["home/.bashrc", "config/nvim/init.lua", "plugins.toml"])
+            ),
+            "Add: 3 files in config, home\n\nNamespaces: config, home\n\n\
+             Files:\n  home/.bashrc\n  config/nvim/init.lua\n  plugins.toml\n"
+        );
+    }
+}
@@ -1,18 +1,31 @@
-use std::path::{Path, PathBuf};
-
 use clap::Args;
 use color_eyre::{eyre::Context, Result};
-use tracing::{debug, error, info};
+use tracing::info;
 
 use crate::{
+    backup::Generation,
+    commands::gc,
+    deployment::{self, Action, Deployment},
+    namespace::Strategy,
     plugin::{self},
     repository::RepositoryBuilder,
+    state::State,
 };
 
-#[derive(Debug, Args)]
-pub struct DeployOptions {}
+#[derive(Debug, Default, Args)]
+pub struct DeployOptions {
+    /// Print what would be deployed, without changing any files.
+    #[clap(long)]
+    dry_run: bool,
+    /// Deploy every namespace with this strategy.
+    #[clap(long, value_enum)]
+    strategy: Option<Strategy>,
+    /// Remove files deployed before that are no longer in the repository.
+    #[clap(long)]
+    prune: bool,
+}
 
-pub fn deploy(repo_builder: RepositoryBuilder, _options: &DeployOptions) -> Result<()> {
+pub fn deploy(repo_builder: RepositoryBuilder, options: &DeployOptions) -> Result<()> {
     info!("Deploying files");
 
     let repository = repo_builder.open()?;
@@ -20,63 +33,119 @@ pub fn deploy(repo_builder: RepositoryBuilder, _options: &DeployOptions) -> Resu
     let namespaces = repository.namespaces()?;
 
     let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
 
     info!("Deploying files");
 
-    for plugin in plugin_map.repository {
-        plugin::call_on_repository(&plugin.cmd, repository.path())
-            .context("Failed to call plugin")?;
+    for plugin in &plugin_map.repository {
+        if options.dry_run {
+            println!("{:<10} {}", "plugin", plugin.cmd);
+            continue;
+        }
+        let invocation = plugin::Invocation {
+            repository: repository.path(),
+            vars: &vars,
+            file: None,
+        };
+        plugin::call_on_repository(plugin, &invocation).context("Failed to call plugin")?;
     }
 
-    for namespace in &namespaces {
-        let mut files = vec![];
-        get_files(&namespace.location, &namespace.location, &mut files, 20)?;
-        'floop: for file in files {
-            for target in &namespace.targets {
-                debug!(
-                    "Deploying file '{}' to '{}'",
-                    file.display(),
-                    target.display()
-                );
-
-                let mut dest = target.join(&file);
-                let mut src = namespace.location.join(&file);
-
-                // Make sure dest directory exists
-                if let Some(parent) = dest.parent() {
-                    if !parent.exists() {
-                        crate::create_dir_all!(parent)?;
-                    }
-                }
-
-                // Run the file contents through plugins.
-                let mut contents = std::fs::read(&src)?;
-                while let Some(plugin) = src
-                    .extension()
-                    .and_then(|ext| plugin_map.file.get(ext.to_str().unwrap()))
+    let previous_state = State::load(repository.path())?;
+    let mut state = State::new(repository.path());
+    let mut backup = Generation::new("deploy")?;
+
+    let deployments = deployment::collect(&namespaces, &plugin_map, &vars, options.strategy)?;
+    for deployment in &deployments {
+        let action = deployment.plan();
+        if deployment.copy_fallback && !matches!(action, Action::Unchanged) {
+            println!(
+                "Copying '{}' instead of linking, because it is run through plugins",
+                deployment.dest.display()
+            );
+        }
+        if options.dry_run {
+            match &action {
+                Action::Skip(reason) => println!(
+                    "{:<10} {} ({reason})",
+                    action.label(),
+                    deployment.dest.display()
+                ),
+                Action::Create(_) | Action::Overwrite(_)
+                    if deployment.strategy != Strategy::Copy =>
                 {
-                    contents = match plugin::call_on_file(&plugin.cmd, contents) {
-                        Ok(contents) => contents,
-                        Err(err) => {
-                            error!(%err, "Calling plugin '{}' failed", &plugin.cmd);
-                            continue 'floop;
-                        }
-                    };
-
-                    // Trim back extension.
-                    dest = dest.with_extension("");
-                    src = src.with_extension("");
+                    println!(
+                        "{:<10} {} -> {} ({})",
+                        action.label(),
+                        deployment.dest.display(),
+                        deployment.source.display(),
+                        deployment.strategy.label()
+                    )
                 }
+                _ => println!("{:<10} {}", action.label(), deployment.dest.display()),
+            }
+            continue;
+        }
 
-                std::fs::write(&dest, contents)
-                    .wrap_err(format!("Failed to write to '{}'", dest.display()))?;
-                // crate::copy_file!(&src, &dest).context(format!(
-                //     "Failed to copy '{}' to '{}'",
-                //     src.display(),
-                //     dest.display()
-                // ))?;
+        if let Action::Create(_) | Action::Overwrite(_) = action {
+            let result = backup
+                .save(&deployment.dest)
+                .wrap_err("Failed to back up file")
+                .and_then(|()| deployment.apply(&action));
+            if let Err(err) = result {
+                // Keep the backups of the files that were already overwritten.
+                backup.finish().context("Failed to save backup")?;
+                return Err(err);
             }
         }
+
+        if let Action::Skip(reason) = &action {
+            eprintln!("Skipping '{}': {reason}", deployment.dest.display());
+            // Keep tracking files that were deployed before.
+            if let Some(file) = previous_state
+                .as_ref()
+                .and_then(|s| s.get(&deployment.dest))
+            {
+                state.files.push(file.clone());
+            }
+            continue;
+        }
+        let created = matches!(action, Action::Create(_))
+            || previous_state
+                .as_ref()
+                .and_then(|s| s.get(&deployment.dest))
+                .is_some_and(|file| file.created);
+        state.files.push(deployment.record(created)?);
+    }
+
+    // Keep tracking files that are no longer deployed, e.g. from a disabled namespace,
+    // so they can be removed once they are gone from the repository.
+    let deployed = deployments
+        .iter()
+        .map(|deployment| deployment.dest.clone())
+        .collect::<Vec<_>>();
+    let tracked = deployment::all_dests(&namespaces, &plugin_map, &vars)?;
+    let undeployed = previous_state
+        .into_iter()
+        .flat_map(|s| s.files)
+        .filter(|file| !deployed.contains(&file.dest))
+        .collect::<Vec<_>>();
+    let orphan_count = undeployed
+        .iter()
+        .filter(|file| gc::is_orphan(file, &tracked))
+        .count();
+    state.files.extend(undeployed);
+
+    if options.prune {
+        gc::prune(&mut state, &tracked, options.dry_run, &mut backup)?;
+    } else if orphan_count > 0 {
+        println!(
+            "{orphan_count} deployed files are no longer in the repository, run `fig gc` or `fig deploy --prune` to remove them"
+        );
+    }
+
+    if !options.dry_run {
+        backup.finish().context("Failed to save backup")?;
+        state.save().context("Failed to save deployment state")?;
     }
 
     info!("Deploying files successful");
@@ -84,30 +153,30 @@ pub fn deploy(repo_builder: RepositoryBuilder, _options: &DeployOptions) -> Resu
     Ok(())
 }
 
-fn get_files(
-    parent_dir: &PathBuf,
-    entry: &Path,
-    files: &mut Vec<PathBuf>,
-    depth: u8,
+/// Deploy only `deployments`, e.g. the files a command just changed, recording them in `state`.
+pub fn deploy_files(
+    deployments: &[Deployment],
+    state: &mut State,
+    backup: &mut Generation,
 ) -> Result<()> {
-    if depth == 0 {
-        return Ok(());
-    } else if entry.is_dir() {
-        for entry in entry.read_dir()? {
-            let entry = entry?.path();
-            get_files(parent_dir, &entry, files, depth - 1)?;
+    for deployment in deployments {
+        let action = deployment.plan();
+        if let Action::Skip(reason) = &action {
+            eprintln!("Skipping '{}': {reason}", deployment.dest.display());
+            continue;
         }
-
-        return Ok(());
-    } else if let Some(extension) = entry.extension() {
-        if extension == "fig" {
-            return Ok(());
+        if let Action::Create(_) | Action::Overwrite(_) = action {
+            backup
+                .save(&deployment.dest)
+                .wrap_err("Failed to back up file")?;
+            deployment.apply(&action)?;
         }
+        println!("{:<10} {}", action.label(), deployment.dest.display());
+
+        let created = matches!(action, Action::Create(_))
+            || state.get(&deployment.dest).is_some_and(|file| file.created);
+        state.files.retain(|file| file.dest != deployment.dest);
+        state.files.push(deployment.record(created)?);
     }
-    // Strip fig dir
-    let path = entry
-        .strip_prefix(parent_dir)
-        .context("Failed to strip path prefix")?;
-    files.push(path.to_path_buf());
     Ok(())
 }
@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use color_eyre::Result;
+use similar::{ChangeTag, TextDiff};
+
+use crate::{
+    deployment::{self, Action, Deployment},
+    repository::RepositoryBuilder,
+};
+
+#[derive(Debug, Args)]
+pub struct DiffOptions {
+    /// Only show files deployed to these paths
+    paths: Vec<PathBuf>,
+    /// Only show files from certain namespace
+    #[clap(short, long)]
+    namespace: Vec<String>,
+    /// Only show a summary of changed files
+    #[clap(long)]
+    stat: bool,
+}
+
+pub fn diff(repo_builder: RepositoryBuilder, options: &DiffOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+
+    let namespaces = repository
+        .namespaces()?
+        .into_iter()
+        .filter(|ns| {
+            options.namespace.is_empty() || options.namespace.iter().any(|n| n == ns.name())
+        })
+        .collect::<Vec<_>>();
+
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+
+    let paths = options
+        .paths
+        .iter()
+        .map(|path| crate::absolute_path(path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let mut stats = Stats::default();
+    for deployment in deployment::collect(&namespaces, &plugin_map, &vars, None)? {
+        if !paths.is_empty() && !paths.iter().any(|path| deployment.dest.starts_with(path)) {
+            continue;
+        }
+
+        let new = match deployment.plan() {
+            Action::Create(contents) | Action::Overwrite(contents) => contents,
+            Action::Unchanged => continue,
+            Action::Skip(reason) => {
+                eprintln!("Skipping '{}': {reason}", deployment.dest.display());
+                continue;
+            }
+        };
+        let old = if deployment.dest.exists() {
+            Some(std::fs::read(&deployment.dest)?)
+        } else {
+            None
+        };
+
+        if options.stat {
+            stats.add(&deployment, old.as_deref(), &new);
+        } else {
+            print_diff(&deployment, old.as_deref(), &new);
+        }
+    }
+
+    if options.stat {
+        stats.print();
+    }
+
+    Ok(())
+}
+
+fn print_diff(deployment: &Deployment, old: Option<&[u8]>, new: &[u8]) {
+    let dest = deployment.dest.display().to_string();
+    let old_header = if old.is_some() {
+        dest.as_str()
+    } else {
+        "/dev/null"
+    };
+
+    match (text(old.unwrap_or_default()), text(new)) {
+        (Some(old), Some(new)) => {
+            let diff = TextDiff::from_lines(old, new);
+            print!(
+                "{}",
+                diff.unified_diff()
+                    .context_radius(3)
+                    .header(old_header, &dest)
+            );
+        }
+        _ => println!("Binary files {old_header} and {dest} differ"),
+    }
+}
+
+fn text(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes).ok()
+}
+
+#[derive(Default)]
+struct Stats {
+    files: Vec<(PathBuf, Option<(usize, usize)>)>,
+}
+
+impl Stats {
+    fn add(&mut self, deployment: &Deployment, old: Option<&[u8]>, new: &[u8]) {
+        let counts = match (text(old.unwrap_or_default()), text(new)) {
+            (Some(old), Some(new)) => {
+                let diff = TextDiff::from_lines(old, new);
+                let mut insertions = 0;
+                let mut deletions = 0;
+                for change in diff.iter_all_changes() {
+                    match change.tag() {
+                        ChangeTag::Insert => insertions += 1,
+                        ChangeTag::Delete => deletions += 1,
+                        ChangeTag::Equal => {}
+                    }
+                }
+                Some((insertions, deletions))
+            }
+            _ => None,
+        };
+        self.files.push((deployment.dest.clone(), counts));
+    }
+
+    fn print(&self) {
+        let width = self
+            .files
+            .iter()
+            .map(|(path, _)| display_len(path))
+            .max()
+            .unwrap_or_default();
+
+        let mut insertions = 0;
+        let mut deletions = 0;
+        for (path, counts) in &self.files {
+            let path = path.display().to_string();
+            match counts {
+                Some((ins, del)) => {
+                    insertions += ins;
+                    deletions += del;
+                    println!(
+                        " {path:width$} | {:>5} {}{}",
+                        ins + del,
+                        "+".repeat((*ins).min(40)),
+                        "-".repeat((*del).min(40))
+                    );
+                }
+                None => println!(" {path:width$} | Bin"),
+            }
+        }
+        println!(
+            " {} files changed, {insertions} insertions(+), {deletions} deletions(-)",
+            self.files.len()
+        );
+    }
+}
+
+fn display_len(path: &Path) -> usize {
+    path.display().to_string().len()
+}
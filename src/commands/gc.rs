@@ -0,0 +1,327 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use tracing::info;
+
+use crate::{
+    backup::Generation,
+    deployment,
+    repository::RepositoryBuilder,
+    state::{self, DeployedFile, State},
+};
+
+#[derive(Debug, Args)]
+pub struct GcOptions {
+    /// Print the files that would be removed, without removing them.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+pub fn gc(repo_builder: RepositoryBuilder, options: &GcOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+
+    let Some(mut state) = State::load(repository.path())? else {
+        println!("Nothing has been deployed from this repository");
+        return Ok(());
+    };
+
+    let namespaces = repository.namespaces()?;
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+    let tracked = deployment::all_dests(&namespaces, &plugin_map, &vars)?;
+
+    let mut backup = Generation::new("gc")?;
+    let removed = prune(&mut state, &tracked, options.dry_run, &mut backup)?;
+
+    if !options.dry_run {
+        backup.finish().context("Failed to save backup")?;
+        state.save().context("Failed to save deployment state")?;
+        if removed > 0 {
+            println!(
+                "Removed {removed} files, run `fig rollback -g {}` to undo",
+                backup.id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove deployed files whose source is gone from the repository, and stop tracking them.
+/// `tracked` is where the repository deploys to on any machine, see [`deployment::all_dests`].
+///
+/// Files are only removed if fig created them and they were not changed since, and they
+/// are backed up first. Returns how many files were (or would be) removed.
+pub fn prune(
+    state: &mut State,
+    tracked: &[PathBuf],
+    dry_run: bool,
+    backup: &mut Generation,
+) -> Result<usize> {
+    let repository = state
+        .repository
+        .canonicalize()
+        .unwrap_or_else(|_| state.repository.clone());
+
+    let mut removed = 0;
+    let mut files = vec![];
+    for file in std::mem::take(&mut state.files) {
+        if !is_orphan(&file, tracked) {
+            files.push(file);
+            continue;
+        }
+
+        match orphan(&file, &repository)? {
+            Orphan::Missing => {}
+            Orphan::Keep(reason) => {
+                println!("{:<10} {} ({reason})", "keep", file.dest.display());
+            }
+            Orphan::Remove => {
+                println!("{:<10} {}", "remove", file.dest.display());
+                removed += 1;
+                if !dry_run {
+                    backup.save(&file.dest).wrap_err("Failed to back up file")?;
+                    std::fs::remove_file(&file.dest)
+                        .wrap_err(format!("Failed to remove '{}'", file.dest.display()))?;
+                    info!(path = %file.dest.display(), "Removed orphaned file");
+                }
+            }
+        }
+        if dry_run {
+            files.push(file);
+        }
+    }
+    state.files = files;
+
+    if removed == 0 {
+        println!("No files to remove");
+    }
+    Ok(removed)
+}
+
+/// Whether the file was deployed from a file that is gone from the repository.
+pub fn is_orphan(file: &DeployedFile, tracked: &[PathBuf]) -> bool {
+    !tracked.contains(&file.dest) && !file.source.exists()
+}
+
+pub enum Orphan {
+    /// The file is already gone from the system.
+    Missing,
+    /// The file is left on the system, with the reason why.
+    Keep(&'static str),
+    Remove,
+}
+
+/// Whether a file that is not deployed anymore can be removed from the system.
+/// `repository` has its links resolved, like the targets of symlinks fig creates.
+pub fn orphan(file: &DeployedFile, repository: &std::path::Path) -> Result<Orphan> {
+    if !file.dest.exists() && !file.dest.is_symlink() {
+        return Ok(Orphan::Missing);
+    }
+    if !file.created {
+        return Ok(Orphan::Keep("it existed before it was deployed"));
+    }
+
+    if file.dest.is_symlink() {
+        // The file it linked to was most likely removed, so only the link is left.
+        let target = std::fs::read_link(&file.dest)?;
+        return Ok(if target.starts_with(repository) {
+            Orphan::Remove
+        } else {
+            Orphan::Keep("it links somewhere else")
+        });
+    }
+
+    let contents =
+        std::fs::read(&file.dest).wrap_err(format!("Failed to read '{}'", file.dest.display()))?;
+    Ok(if state::hash(&contents) == file.hash {
+        Orphan::Remove
+    } else {
+        Orphan::Keep("it was changed since it was deployed")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// A file deployed from `source` to `dest`, with the contents it was deployed with.
+    fn deployed(source: &Path, dest: &Path, contents: &str, created: bool) -> DeployedFile {
+        DeployedFile {
+            dest: dest.to_path_buf(),
+            source: source.to_path_buf(),
+            namespace: "home".to_string(),
+            plugins: vec![],
+            source_hash: state::hash(contents.as_bytes()),
+            hash: state::hash(contents.as_bytes()),
+            created,
+        }
+    }
+
+    struct Dirs {
+        _dir: tempfile::TempDir,
+        repository: PathBuf,
+        system: PathBuf,
+    }
+
+    fn dirs() -> Dirs {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = dir.path().join("repository");
+        let system = dir.path().join("system");
+        std::fs::create_dir_all(repository.join("home")).unwrap();
+        std::fs::create_dir_all(&system).unwrap();
+        Dirs {
+            repository: repository.canonicalize().unwrap(),
+            system: system.canonicalize().unwrap(),
+            _dir: dir,
+        }
+    }
+
+    #[test]
+    fn orphan_missing() {
+        let dirs = dirs();
+        let file = deployed(
+            &dirs.repository.join("home/a"),
+            &dirs.system.join("a"),
+            "a",
+            true,
+        );
+        assert!(matches!(
+            orphan(&file, &dirs.repository).unwrap(),
+            Orphan::Missing
+        ));
+    }
+
+    #[test]
+    fn orphan_not_created_by_fig() {
+        let dirs = dirs();
+        let dest = dirs.system.join("a");
+        std::fs::write(&dest, "a").unwrap();
+        let file = deployed(&dirs.repository.join("home/a"), &dest, "a", false);
+        assert!(matches!(
+            orphan(&file, &dirs.repository).unwrap(),
+            Orphan::Keep(_)
+        ));
+    }
+
+    #[test]
+    fn orphan_changed() {
+        let dirs = dirs();
+        let dest = dirs.system.join("a");
+        std::fs::write(&dest, "changed").unwrap();
+        let file = deployed(&dirs.repository.join("home/a"), &dest, "a", true);
+        assert!(matches!(
+            orphan(&file, &dirs.repository).unwrap(),
+            Orphan::Keep(_)
+        ));
+    }
+
+    #[test]
+    fn orphan_unchanged() {
+        let dirs = dirs();
+        let dest = dirs.system.join("a");
+        std::fs::write(&dest, "a").unwrap();
+        let file = deployed(&dirs.repository.join("home/a"), &dest, "a", true);
+        assert!(matches!(
+            orphan(&file, &dirs.repository).unwrap(),
+            Orphan::Remove
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn orphan_links() {
+        let dirs = dirs();
+        let source = dirs.repository.join("home/a");
+
+        // The file in the repository is gone, so the link is broken.
+        let dest = dirs.system.join("a");
+        std::os::unix::fs::symlink(&source, &dest).unwrap();
+        let file = deployed(&source, &dest, "a", true);
+        assert!(matches!(
+            orphan(&file, &dirs.repository).unwrap(),
+            Orphan::Remove
+        ));
+
+        // The link was replaced with one that points somewhere else.
+        let elsewhere = dirs.system.join("elsewhere");
+        std::fs::write(&elsewhere, "a").unwrap();
+        let dest = dirs.system.join("b");
+        std::os::unix::fs::symlink(&elsewhere, &dest).unwrap();
+        let file = deployed(&source, &dest, "a", true);
+        assert!(matches!(
+            orphan(&file, &dirs.repository).unwrap(),
+            Orphan::Keep(_)
+        ));
+    }
+
+    #[test]
+    fn only_files_without_source_are_orphans() {
+        let dirs = dirs();
+        let source = dirs.repository.join("home/a");
+        let dest = dirs.system.join("a");
+        let file = deployed(&source, &dest, "a", true);
+
+        assert!(is_orphan(&file, &[]));
+        // Deployed to on another machine, from an inactive target.
+        assert!(!is_orphan(&file, std::slice::from_ref(&dest)));
+        // Still in the repository, e.g. in a disabled namespace.
+        std::fs::write(&source, "a").unwrap();
+        assert!(!is_orphan(&file, &[]));
+    }
+
+    /// A state with a file that can be removed, and files that must be kept.
+    fn state(dirs: &Dirs) -> State {
+        let mut state = State::new(&dirs.repository);
+        for (name, in_repository, created) in [
+            ("removable", false, true),
+            ("not-created", false, false),
+            ("disabled", true, true),
+        ] {
+            let source = dirs.repository.join("home").join(name);
+            let dest = dirs.system.join(name);
+            if in_repository {
+                std::fs::write(&source, name).unwrap();
+            }
+            std::fs::write(&dest, name).unwrap();
+            state.files.push(deployed(&source, &dest, name, created));
+        }
+        state
+    }
+
+    #[test]
+    fn prune_dry_run() {
+        let dirs = dirs();
+        let mut state = state(&dirs);
+        let mut backup = Generation::new("test").unwrap();
+
+        assert_eq!(prune(&mut state, &[], true, &mut backup).unwrap(), 1);
+        assert_eq!(state.files.len(), 3);
+        assert!(backup.files.is_empty());
+        for name in ["removable", "not-created", "disabled"] {
+            assert!(dirs.system.join(name).exists(), "{name}");
+        }
+    }
+
+    #[test]
+    fn prune_keeps_files_fig_did_not_create() {
+        let dirs = dirs();
+        let mut state = state(&dirs);
+        state.files.retain(|file| !file.dest.ends_with("removable"));
+        let mut backup = Generation::new("test").unwrap();
+
+        assert_eq!(prune(&mut state, &[], false, &mut backup).unwrap(), 0);
+        assert!(dirs.system.join("not-created").exists());
+        assert!(dirs.system.join("disabled").exists());
+        // The file is left alone, and forgotten, as its source is gone.
+        let tracked = state
+            .files
+            .iter()
+            .map(|file| &file.dest)
+            .collect::<Vec<_>>();
+        assert_eq!(tracked, [&dirs.system.join("disabled")]);
+    }
+}
@@ -4,7 +4,12 @@ use clap::Args;
 use color_eyre::{eyre::Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::{namespace::Namespace, repository::RepositoryBuilder};
+use crate::{
+    facts::Facts,
+    namespace::Namespace,
+    repository::{GitStatus, RepositoryBuilder},
+    state::{Drift, State},
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Info {
@@ -13,6 +18,11 @@ pub struct Info {
     pub floating_namespaces: Vec<String>,
     pub repository_path: PathBuf,
     pub log_path: PathBuf,
+    pub facts: Facts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drift: Option<Drift>,
 }
 
 impl Info {
@@ -26,6 +36,13 @@ impl Info {
                 floating_namespaces: repository.floating_namespaces()?,
                 repository_path,
                 log_path,
+                facts: Facts::get().clone(),
+                git: Some(
+                    repository
+                        .git_status()
+                        .context("Failed to read git status")?,
+                ),
+                drift: None,
             }),
             Err(_) => Ok(Self {
                 initialised: false,
@@ -33,6 +50,9 @@ impl Info {
                 floating_namespaces: vec![],
                 repository_path,
                 log_path,
+                facts: Facts::get().clone(),
+                git: None,
+                drift: None,
             }),
         }
     }
@@ -43,10 +63,23 @@ impl Info {
 pub struct InfoOptions {
     #[clap(long)]
     json: bool,
+    /// Compare the system and repository with the last deploy.
+    #[clap(long)]
+    drift: bool,
 }
 
 pub fn info(repo_builder: RepositoryBuilder, options: &InfoOptions) -> Result<()> {
-    let info = Info::gather(repo_builder)?;
+    let repository_path = repo_builder.path().clone();
+    let mut info = Info::gather(repo_builder)?;
+
+    let mut deployed_at = None;
+    if options.drift && info.initialised {
+        let state = State::load(&repository_path)?.unwrap_or_default();
+        if !state.files.is_empty() {
+            deployed_at = Some(state.deployed_at());
+        }
+        info.drift = Some(state.drift().context("Failed to compare deployed files")?);
+    }
 
     if options.json {
         let json = serde_json::to_string_pretty(&info).context("Failed to serialize Info")?;
@@ -59,6 +92,10 @@ pub fn info(repo_builder: RepositoryBuilder, options: &InfoOptions) -> Result<()
     println!("initialised: {}", info.initialised);
     println!("location: {}", info.repository_path.display());
     println!("log file: {}", info.log_path.display());
+    println!(
+        "machine: {}@{} ({} {})",
+        info.facts.username, info.facts.hostname, info.facts.os, info.facts.arch
+    );
 
     println!();
 
@@ -66,14 +103,15 @@ pub fn info(repo_builder: RepositoryBuilder, options: &InfoOptions) -> Result<()
     for namespace in &info.namespaces {
         let file_name = namespace.location.file_name().unwrap().to_str().unwrap();
         println!(
-            "{}: {}",
+            "{}{}: {}",
             file_name,
+            if namespace.enabled { "" } else { " (disabled)" },
             match namespace.targets.len() {
                 0 => {
                     "<no targets>".to_string()
                 }
                 1 => {
-                    namespace.targets.get(0).unwrap().display().to_string()
+                    namespace.targets.first().unwrap().display().to_string()
                 }
                 2.. => {
                     namespace
@@ -81,10 +119,13 @@ pub fn info(repo_builder: RepositoryBuilder, options: &InfoOptions) -> Result<()
                         .iter()
                         .map(|p| p.display().to_string())
                         .collect::<Vec<String>>()
-                        .join(&format!("{}\n", " ".repeat(file_name.len() + 2)))
+                        .join(&format!("\n{}", " ".repeat(file_name.len() + 2)))
                 }
             }
         );
+        if let Some(error) = &namespace.error {
+            println!("{}error: {error}", " ".repeat(file_name.len() + 2));
+        }
     }
 
     if !info.floating_namespaces.is_empty() {
@@ -92,10 +133,83 @@ pub fn info(repo_builder: RepositoryBuilder, options: &InfoOptions) -> Result<()
             "== Floating namespaces {} ==",
             info.floating_namespaces.len()
         );
-        for ns in info.floating_namespaces {
+        for ns in &info.floating_namespaces {
             println!("{ns}");
         }
     }
 
+    if let Some(git) = &info.git {
+        println!();
+        print_git_status(git);
+    }
+
+    if let Some(drift) = &info.drift {
+        println!();
+        println!("== Drift ==");
+        match deployed_at {
+            Some(deployed_at) => println!(
+                "last deploy: {}",
+                humantime::format_rfc3339_seconds(deployed_at)
+            ),
+            None => println!("last deploy: <never>"),
+        }
+        if drift.is_empty() {
+            println!("Everything is up to date");
+        }
+        for path in &drift.modified {
+            println!("modified on system:       {}", path.display());
+        }
+        for path in &drift.missing {
+            println!("missing from system:      {}", path.display());
+        }
+        for path in &drift.changed {
+            println!("changed in repository:    {}", path.display());
+        }
+        for path in &drift.removed {
+            println!("removed from repository:  {}", path.display());
+        }
+    }
+
     Ok(())
 }
+
+fn print_git_status(git: &GitStatus) {
+    println!("== Git ==");
+    let branch = git.branch.as_deref().unwrap_or("<detached HEAD>");
+    match &git.upstream {
+        Some(upstream) => println!(
+            "branch: {branch} ({upstream}, {} ahead, {} behind)",
+            git.ahead, git.behind
+        ),
+        None => println!("branch: {branch} (no upstream)"),
+    }
+    if let Some(operation) = &git.in_progress {
+        let hint = match operation.as_str() {
+            "bisect" => "`fig cmd -- git bisect reset`".to_string(),
+            _ => format!("`fig cmd -- git {operation} --continue` or `--abort`"),
+        };
+        println!("{operation} in progress, finish it with {hint}");
+    }
+
+    if git.namespaces.is_empty() {
+        println!("Everything is committed");
+    }
+    for namespace in &git.namespaces {
+        match &namespace.namespace {
+            Some(name) => println!("-- {name} --"),
+            None => println!("-- <repository> --"),
+        }
+        for path in &namespace.conflicted {
+            println!("conflicted: {}", path.display());
+        }
+        for path in &namespace.staged {
+            println!("staged:     {}", path.display());
+        }
+        for path in &namespace.modified {
+            println!("modified:   {}", path.display());
+        }
+        for path in &namespace.untracked {
+            println!("untracked:  {}", path.display());
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+
+use crate::{commands::commit::short, deployment, repository::RepositoryBuilder};
+
+#[derive(Debug, Args)]
+pub struct LogOptions {
+    /// The file or directory on your system
+    path: PathBuf,
+    /// Only show this many commits
+    #[clap(short = 'n', long)]
+    max_count: Option<usize>,
+}
+
+pub fn log(repo_builder: RepositoryBuilder, options: &LogOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+
+    let path = crate::absolute_path(&options.path)?;
+    let tracked = deployment::tracked(&repository, &path, &plugin_map, &vars)?;
+
+    let entries = repository
+        .log(&tracked.sources, options.max_count)
+        .context("Failed to read history")?;
+    if entries.is_empty() {
+        println!("'{}' has not been committed yet", path.display());
+        return Ok(());
+    }
+    for entry in entries {
+        println!(
+            "{}  {}  {:<16} {}",
+            short(entry.id),
+            humantime::format_rfc3339_seconds(entry.time),
+            entry.author,
+            entry.summary
+        );
+    }
+
+    Ok(())
+}
@@ -1,9 +1,21 @@
 pub mod add;
+pub mod capture;
 pub mod clone;
 pub mod cmd;
+pub mod commit;
 pub mod deploy;
+pub mod diff;
+pub mod gc;
 pub mod info;
 pub mod init;
 pub mod list;
+pub mod log;
+pub mod mv;
 pub mod namespace;
+pub mod pull;
 pub mod purge;
+pub mod restore;
+pub mod rm;
+pub mod rollback;
+pub mod secret;
+pub mod sync;
@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::{
+    eyre::{bail, ensure, eyre, Context},
+    Result,
+};
+use tracing::info;
+
+use crate::{
+    backup::Generation,
+    commands::{
+        commit::auto_commit,
+        deploy::deploy_files,
+        gc::{self, Orphan},
+    },
+    deployment,
+    namespace::determine_namespace,
+    repository::RepositoryBuilder,
+    state::State,
+};
+
+#[derive(Debug, Args)]
+pub struct MvOptions {
+    /// The file or directory on your system
+    from: PathBuf,
+    /// Where it should be deployed to instead, or a directory to move it into
+    to: PathBuf,
+    /// Deploy the file to its new place, and remove it from the old one
+    #[clap(long)]
+    deploy: bool,
+}
+
+pub fn mv(repo_builder: RepositoryBuilder, options: &MvOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+
+    let from = crate::absolute_path(&options.from)?;
+    let tracked = deployment::tracked(&repository, &from, &plugin_map, &vars)?;
+    let [source] = &tracked.sources[..] else {
+        bail!("'{}' is deployed from more than one file", from.display());
+    };
+
+    // Like `mv`, moving into a directory keeps the name.
+    let mut to = crate::absolute_path(&options.to)?;
+    let into_dir = options
+        .to
+        .as_os_str()
+        .to_string_lossy()
+        .ends_with(['/', '\\']);
+    if to.is_dir() || into_dir {
+        to = to.join(from.file_name().ok_or_else(|| eyre!("Can not move '/'"))?);
+    }
+
+    let to_namespace = determine_namespace(&repository, &to)?;
+    let relative = to_namespace
+        .targets
+        .iter()
+        .find_map(|target| to.strip_prefix(target).ok())
+        .ok_or_else(|| eyre!("'{}' has no namespace", to.display()))?;
+
+    // Keep the extensions of plugins, e.g. `.tmpl`, which are not part of the deployed name.
+    let mut new_source = to_namespace.location.join(relative).into_os_string();
+    if source.is_file() {
+        let source_name = source.file_name().unwrap().to_string_lossy();
+        let dest_name = from.file_name().unwrap().to_string_lossy();
+        if let Some(extensions) = source_name.strip_prefix(&*dest_name) {
+            new_source.push(extensions);
+        }
+    }
+    let new_source = PathBuf::from(new_source);
+
+    ensure!(
+        !new_source.exists(),
+        "'{}' already exists in the repository",
+        new_source.display()
+    );
+
+    if let Some(parent) = new_source.parent() {
+        crate::create_dir_all_if_not_exists!(parent)?;
+    }
+    std::fs::rename(source, &new_source).wrap_err(format!(
+        "Failed to move '{}' to '{}'",
+        source.display(),
+        new_source.display()
+    ))?;
+    repository
+        .rename_in_index(source, &new_source)
+        .context("Failed to move files in git")?;
+    info!(from = %source.display(), to = %new_source.display(), "Moved file");
+    println!(
+        "{:<10} {} -> {}",
+        "move",
+        source.display(),
+        new_source.display()
+    );
+    auto_commit(&repository, "Move", &[source.clone(), new_source.clone()])?;
+
+    if !options.deploy {
+        if tracked
+            .deployments
+            .iter()
+            .any(|deployment| deployment.dest.exists() || deployment.dest.is_symlink())
+        {
+            println!("Run `fig deploy --prune` to deploy it to its new place, and remove it from the old one");
+        }
+        return Ok(());
+    }
+
+    let mut state =
+        State::load(repository.path())?.unwrap_or_else(|| State::new(repository.path()));
+    let mut backup = Generation::new("mv")?;
+
+    let deployments = deployment::collect(
+        std::slice::from_ref(&to_namespace),
+        &plugin_map,
+        &vars,
+        None,
+    )?
+    .into_iter()
+    .filter(|deployment| deployment.dest.starts_with(&to))
+    .collect::<Vec<_>>();
+
+    deploy_files(&deployments, &mut state, &mut backup)?;
+
+    let repository_path = crate::absolute_path(repository.path())?;
+    for old in &tracked.deployments {
+        if deployments.iter().any(|new| new.dest == old.dest) {
+            continue;
+        }
+        let recorded = state
+            .files
+            .iter()
+            .position(|file| file.dest == old.dest)
+            .map(|i| state.files.remove(i));
+        // Like `fig gc`, only remove files fig created, and that were not changed since.
+        let orphan = match &recorded {
+            Some(file) => gc::orphan(file, &repository_path)?,
+            None if old.dest.exists() || old.dest.is_symlink() => {
+                Orphan::Keep("it was not deployed by fig")
+            }
+            None => Orphan::Missing,
+        };
+        match orphan {
+            Orphan::Missing => {}
+            Orphan::Keep(reason) => {
+                println!("{:<10} {} ({reason})", "keep", old.dest.display());
+            }
+            Orphan::Remove => {
+                backup.save(&old.dest).wrap_err("Failed to back up file")?;
+                std::fs::remove_file(&old.dest)
+                    .wrap_err(format!("Failed to remove '{}'", old.dest.display()))?;
+                println!("{:<10} {}", "remove", old.dest.display());
+            }
+        }
+    }
+
+    backup.finish().context("Failed to save backup")?;
+    state.save().context("Failed to save deployment state")?;
+
+    Ok(())
+}
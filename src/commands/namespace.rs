@@ -1,11 +1,18 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::{Args, Subcommand};
-use color_eyre::eyre::{ensure, Context};
+use color_eyre::eyre::{bail, ensure, Context};
 use color_eyre::Result;
 
-use crate::repository::RepositoryBuilder;
+use crate::{
+    commands::commit::auto_commit,
+    facts::Facts,
+    namespace::{
+        ConditionalTarget, NamespaceConfig, OneOrMany, Target, CONFIG_FILE, LEGACY_CONFIG_FILE,
+    },
+    repository::{Repository, RepositoryBuilder},
+};
 
 #[derive(Debug, Args)]
 pub struct NamespaceOptions {
@@ -29,6 +36,41 @@ pub enum Command {
     /// Remove a namespace.
     #[clap(alias = "rm")]
     Remove { name: String },
+    /// Set where a namespace is deployed to, replacing its targets.
+    ///
+    /// Without arguments, asks for a target for every namespace that has none.
+    Set {
+        #[clap(requires = "path")]
+        name: Option<String>,
+        /// The target, which can use variables such as `~` or `{{config_dir}}`
+        path: Option<String>,
+        /// Only use the target on this machine, keeping the targets of other machines
+        #[clap(long)]
+        host: bool,
+    },
+    /// Add another target to a namespace.
+    AddTarget {
+        name: String,
+        /// The target, which can use variables such as `~` or `{{config_dir}}`
+        path: String,
+        /// Only use the target on this machine
+        #[clap(long)]
+        host: bool,
+    },
+    /// Remove a target from a namespace.
+    RemoveTarget {
+        name: String,
+        /// The target, as written in the namespace or as the path it expands to
+        path: String,
+    },
+    /// Rename a namespace.
+    #[clap(alias = "mv")]
+    Rename { name: String, new_name: String },
+    /// Convert namespace.fig files to namespace.toml.
+    Migrate {
+        /// Only migrate these namespaces
+        names: Vec<String>,
+    },
 }
 
 pub fn namespace_cli(repo_builder: RepositoryBuilder, options: &NamespaceOptions) -> Result<()> {
@@ -52,8 +94,11 @@ pub fn namespace_cli(repo_builder: RepositoryBuilder, options: &NamespaceOptions
                             .to_str()
                             .unwrap(),
                         match namespace.targets.len() {
+                            0 => {
+                                "<no targets>".to_string()
+                            }
                             1 => {
-                                namespace.targets.get(0).unwrap().display().to_string()
+                                namespace.targets.first().unwrap().display().to_string()
                             }
                             2.. => {
                                 namespace
@@ -62,31 +107,36 @@ pub fn namespace_cli(repo_builder: RepositoryBuilder, options: &NamespaceOptions
                                     .map(|p| p.display().to_string())
                                     .collect::<Vec<String>>()
                                     .join(&format!(
-                                        "{}\n",
+                                        "\n{}",
                                         " ".repeat(14 /* Align the targets with each other */)
                                     ))
                             }
-                            // TODO: Namespaces with no listed targets will completely break the program and it is not at all reported to the user.
-                            _ => panic!(),
                         }
                     );
+                    if let Some(error) = &namespace.error {
+                        println!("{:12}  error: {error}", "");
+                    }
                 }
             }
             Ok(())
         }
         Command::Add { name, path } => {
             let dir = repository.path().join(name);
-            let namespace_file = dir.join("namespace.fig");
 
             crate::create_dir_all!(&dir).context("Failed to create namespace directory")?;
 
             let path = path.canonicalize()?;
 
-            std::fs::write(namespace_file, path.display().to_string())
-                .context("Failed to write to namespace file")?;
+            NamespaceConfig {
+                targets: vec![Target::Path(path.clone())],
+                ..Default::default()
+            }
+            .write(&dir)
+            .context("Failed to write to namespace file")?;
 
             tracing::info!(%name, path = %path.display(), "Created namespace");
             println!("Added namespace {}: {}", name, path.display());
+            auto_commit(&repository, &format!("Add namespace {name}"), &[dir])?;
 
             Ok(())
         }
@@ -122,8 +172,307 @@ pub fn namespace_cli(repo_builder: RepositoryBuilder, options: &NamespaceOptions
                 .context("Failed to remove namespace directory")?;
             tracing::info!(%name, path = %namespace_root.display(), "Removed namespace");
             println!("Removed namespace {}: {}", name, namespace_root.display());
+            auto_commit(
+                &repository,
+                &format!("Remove namespace {name}"),
+                &[namespace_root],
+            )?;
+
+            Ok(())
+        }
+        Command::Set {
+            name: Some(name),
+            path: Some(path),
+            host,
+        } => {
+            let dir = namespace_dir(&repository, name)?;
+            let target = parse_target(&repository, path)?;
+            let this_host = host.then(|| Facts::get().hostname.clone());
+
+            update_config(&dir, |config| {
+                match &this_host {
+                    // Only replace the targets that were set for this machine.
+                    Some(hostname) => config.targets.retain(|t| !is_host_target(t, hostname)),
+                    None => config.targets.clear(),
+                }
+                config
+                    .targets
+                    .push(make_target(target.clone(), this_host.clone()));
+                Ok(())
+            })?;
+
+            tracing::info!(%name, path = %target.display(), "Set namespace target");
+            println!("Set namespace {}: {}", name, target.display());
+            auto_commit(&repository, &format!("Set target of {name}"), &[dir])?;
+            Ok(())
+        }
+        Command::Set { .. } => set_floating(&repository),
+        Command::AddTarget { name, path, host } => {
+            let dir = namespace_dir(&repository, name)?;
+            let target = parse_target(&repository, path)?;
+            let this_host = host.then(|| Facts::get().hostname.clone());
+
+            update_config(&dir, |config| {
+                ensure!(
+                    !config.targets.iter().any(|t| t.path() == target),
+                    "{} is already a target of {name}",
+                    target.display()
+                );
+                config.targets.push(make_target(target.clone(), this_host));
+                Ok(())
+            })?;
+
+            tracing::info!(%name, path = %target.display(), "Added namespace target");
+            println!("Added target to {}: {}", name, target.display());
+            auto_commit(&repository, &format!("Add target to {name}"), &[dir])?;
+            Ok(())
+        }
+        Command::RemoveTarget { name, path } => {
+            let dir = namespace_dir(&repository, name)?;
+            let resolved = crate::expand::expand(path)
+                .ok()
+                .map(|p| resolve(Path::new(&p)));
+
+            let mut remaining = 0;
+            update_config(&dir, |config| {
+                let before = config.targets.len();
+                config.targets.retain(|t| {
+                    let matches = t.path() == Path::new(path)
+                        || crate::expand::expand_path(t.path())
+                            .is_ok_and(|p| Some(resolve(&p)) == resolved);
+                    !matches
+                });
+                ensure!(
+                    config.targets.len() < before,
+                    "{path} is not a target of {name}"
+                );
+                remaining = config.targets.len();
+                Ok(())
+            })?;
+
+            tracing::info!(%name, %path, "Removed namespace target");
+            println!("Removed target from {name}: {path}");
+            if remaining == 0 {
+                println!("Note: {name} has no targets left, and will not be deployed");
+            }
+            auto_commit(&repository, &format!("Remove target from {name}"), &[dir])?;
+            Ok(())
+        }
+        Command::Rename { name, new_name } => {
+            let dir = namespace_dir(&repository, name)?;
+            validate_name(new_name)?;
+            let new_dir = repository.path().join(new_name);
+            ensure!(!new_dir.exists(), "{new_name} already exists");
+
+            std::fs::rename(&dir, &new_dir).context("Failed to rename namespace directory")?;
+
+            tracing::info!(%name, %new_name, "Renamed namespace");
+            println!("Renamed namespace {name} to {new_name}");
+            auto_commit(
+                &repository,
+                &format!("Rename namespace {name} to {new_name}"),
+                &[dir, new_dir],
+            )?;
+            Ok(())
+        }
+        Command::Migrate { names } => {
+            let mut migrated = vec![];
+            for namespace in repository.namespaces()? {
+                let name = namespace.name();
+                if !names.is_empty() && !names.iter().any(|n| n == name) {
+                    continue;
+                }
+
+                let legacy_file = namespace.location.join(LEGACY_CONFIG_FILE);
+                if !legacy_file.exists() {
+                    continue;
+                }
+                if namespace.location.join(CONFIG_FILE).exists() {
+                    println!("Skipping {name}: it already has a {CONFIG_FILE}");
+                    continue;
+                }
+
+                let text = std::fs::read_to_string(&legacy_file)
+                    .context("Failed to read namespace file")?;
+                NamespaceConfig::parse_legacy(&text).write(&namespace.location)?;
+                std::fs::remove_file(&legacy_file)
+                    .context("Failed to remove old namespace file")?;
+
+                tracing::info!(%name, "Migrated namespace");
+                println!("Migrated namespace {name}");
+                migrated.push(namespace.location.clone());
+            }
+
+            auto_commit(&repository, "Migrate namespaces", &migrated)?;
+            if !migrated.is_empty() {
+                println!(
+                    "Note: unlike {LEGACY_CONFIG_FILE}, {CONFIG_FILE} is not ignored by git, and will be committed"
+                );
+            }
 
             Ok(())
         }
     }
 }
+
+/// Ask for a target for each namespace that does not have one, e.g. after cloning.
+pub fn set_floating(repository: &Repository) -> Result<()> {
+    let floating_namespaces = repository.floating_namespaces()?;
+    if floating_namespaces.is_empty() {
+        println!("Every namespace already has a target");
+        return Ok(());
+    }
+
+    println!("Enter where each namespace is deployed to, or leave it empty to skip it.");
+    let mut set = vec![];
+    'namespaces: for name in floating_namespaces {
+        loop {
+            print!("{name}: ");
+            std::io::stdout().flush()?;
+            let mut buf = String::new();
+            if std::io::stdin()
+                .read_line(&mut buf)
+                .context("Failed to read from stdin")?
+                == 0
+            {
+                // End of input, nothing more will be answered.
+                println!();
+                break 'namespaces;
+            }
+            let path = buf.trim();
+            if path.is_empty() {
+                break;
+            }
+
+            match parse_target(repository, path) {
+                Ok(target) => {
+                    update_config(&repository.path().join(&name), |config| {
+                        config.targets = vec![Target::Path(target.clone())];
+                        Ok(())
+                    })?;
+                    tracing::info!(%name, path = %target.display(), "Set namespace target");
+                    set.push(repository.path().join(&name));
+                    break;
+                }
+                Err(e) => println!("{e:#}"),
+            }
+        }
+    }
+
+    auto_commit(repository, "Set targets of namespaces", &set)?;
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    ensure!(!name.is_empty(), "A namespace name can not be empty");
+    ensure!(
+        !name.starts_with('.'),
+        "A namespace name can not start with '.', as hidden directories are ignored"
+    );
+    ensure!(
+        !name.contains(['/', '\\']) && name != "..",
+        "A namespace name can not contain a path separator"
+    );
+    Ok(())
+}
+
+/// The directory of an existing namespace, which might not have a definition yet.
+fn namespace_dir(repository: &Repository, name: &str) -> Result<PathBuf> {
+    validate_name(name)?;
+    let dir = repository.path().join(name);
+    ensure!(dir.is_dir(), "The namespace {name} does not exist");
+    Ok(dir)
+}
+
+/// Check a target given by the user, and return what to write to the namespace.
+/// Targets with variables are written as they are, so they can be shared between machines.
+fn parse_target(repository: &Repository, path: &str) -> Result<PathBuf> {
+    ensure!(!path.is_empty(), "A target can not be empty");
+    let expanded = crate::expand::expand(path).wrap_err(format!("Invalid target '{path}'"))?;
+    let has_variables = expanded != path;
+    // Plain paths can be relative to the current directory, but a variable could mean
+    // something different in another directory.
+    ensure!(
+        !has_variables || Path::new(&expanded).is_absolute(),
+        "Target '{path}' must expand to an absolute path"
+    );
+    let expanded = resolve(Path::new(&expanded));
+
+    if expanded.exists() {
+        ensure!(
+            expanded.is_dir(),
+            "Target '{}' is not a directory",
+            expanded.display()
+        );
+    }
+    let repository_path = resolve(repository.path());
+    if expanded.starts_with(&repository_path) {
+        bail!(
+            "Target '{}' is inside the repository, and can not be deployed to",
+            expanded.display()
+        );
+    }
+    if !expanded.exists() {
+        println!(
+            "Note: '{}' does not exist yet, it will be created when deploying",
+            expanded.display()
+        );
+    }
+
+    Ok(if has_variables {
+        PathBuf::from(path)
+    } else {
+        expanded
+    })
+}
+
+/// Make a path absolute, resolving links in the parts of it that exist.
+fn resolve(path: &Path) -> PathBuf {
+    crate::absolute_path(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn make_target(path: PathBuf, hostname: Option<String>) -> Target {
+    match hostname {
+        Some(hostname) => Target::Conditional(ConditionalTarget {
+            path,
+            hostname: Some(OneOrMany::One(hostname)),
+            ..Default::default()
+        }),
+        None => Target::Path(path),
+    }
+}
+
+/// Whether the target was added with `--host` on the machine called `hostname`.
+fn is_host_target(target: &Target, hostname: &str) -> bool {
+    match target {
+        Target::Path(_) => false,
+        Target::Conditional(condition) => {
+            condition
+                .hostname
+                .as_ref()
+                .is_some_and(|h| h.matches(hostname))
+                && condition.os.is_none()
+                && condition.family.is_none()
+                && condition.arch.is_none()
+                && condition.username.is_none()
+                && condition.env.is_empty()
+        }
+    }
+}
+
+/// Change the definition of the namespace in `dir`, always writing it to namespace.toml.
+fn update_config(dir: &Path, f: impl FnOnce(&mut NamespaceConfig) -> Result<()>) -> Result<()> {
+    let mut config = NamespaceConfig::read(dir)?.unwrap_or_default();
+    f(&mut config)?;
+    config.write(dir)?;
+
+    // Keep a single definition, so the old file can not disagree with the new one.
+    let legacy_file = dir.join(LEGACY_CONFIG_FILE);
+    if legacy_file.exists() {
+        std::fs::remove_file(&legacy_file).context("Failed to remove old namespace file")?;
+        println!(
+            "Note: {LEGACY_CONFIG_FILE} was converted to {CONFIG_FILE}, which will be committed"
+        );
+    }
+    Ok(())
+}
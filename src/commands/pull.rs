@@ -0,0 +1,30 @@
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+
+use crate::{
+    commands::commit::short,
+    repository::{Pull, Repository, RepositoryBuilder},
+};
+
+#[derive(Debug, Args)]
+pub struct PullOptions {
+    /// Fail instead of rebasing local commits, if the branches have diverged.
+    #[clap(long)]
+    ff_only: bool,
+}
+
+pub fn pull(repo_builder: RepositoryBuilder, options: &PullOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+    pull_and_report(&repository, !options.ff_only)
+}
+
+pub fn pull_and_report(repository: &Repository, rebase: bool) -> Result<()> {
+    match repository.pull(rebase).context("Failed to pull changes")? {
+        Pull::UpToDate => println!("Already up to date"),
+        Pull::FastForward(oid) => println!("Fast-forwarded to {}", short(oid)),
+        Pull::Rebased { commits, onto } => {
+            println!("Rebased {commits} local commits onto {}", short(onto))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use tracing::info;
+
+use crate::{
+    backup::Generation,
+    commands::{
+        commit::{auto_commit, short},
+        deploy::deploy_files,
+    },
+    deployment,
+    repository::RepositoryBuilder,
+    state::State,
+};
+
+#[derive(Debug, Args)]
+pub struct RestoreOptions {
+    /// The file or directory on your system
+    path: PathBuf,
+    /// The commit to restore it from, e.g. `HEAD~1` or a commit id from `fig log`
+    #[clap(short, long)]
+    rev: String,
+    /// Deploy the restored files
+    #[clap(long)]
+    deploy: bool,
+}
+
+pub fn restore(repo_builder: RepositoryBuilder, options: &RestoreOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+
+    let path = crate::absolute_path(&options.path)?;
+    let id = repository.rev_parse(&options.rev)?;
+    let sources = match deployment::tracked(&repository, &path, &plugin_map, &vars) {
+        Ok(tracked) => tracked.sources,
+        // The file might have been removed from the repository since.
+        Err(e) => match deployment::removed_sources(&repository, &path, id) {
+            Ok(sources) if !sources.is_empty() => sources,
+            _ => return Err(e),
+        },
+    };
+
+    // Files added since are left alone, like `git restore --source`.
+    let mut restored = vec![];
+    for source in &sources {
+        for (file, contents) in repository.files_at(id, source)? {
+            if std::fs::read(&file).is_ok_and(|current| current == contents) {
+                continue;
+            }
+            if let Some(parent) = file.parent() {
+                crate::create_dir_all_if_not_exists!(parent)?;
+            }
+            std::fs::write(&file, contents)
+                .wrap_err(format!("Failed to write to '{}'", file.display()))?;
+            info!(path = %file.display(), %id, "Restored file");
+            println!("{:<10} {}", "restore", file.display());
+            restored.push(file);
+        }
+    }
+
+    if restored.is_empty() {
+        println!("'{}' is the same as in {}", path.display(), short(id));
+        return Ok(());
+    }
+    auto_commit(
+        &repository,
+        &format!("Restore from {}", short(id)),
+        &restored,
+    )?;
+
+    if !options.deploy {
+        println!("Run `fig deploy` to deploy the restored files");
+        return Ok(());
+    }
+
+    // Restoring a directory can bring back files that were not deployed before.
+    let deployments = deployment::tracked(&repository, &path, &plugin_map, &vars)?.deployments;
+    let mut state =
+        State::load(repository.path())?.unwrap_or_else(|| State::new(repository.path()));
+    let mut backup = Generation::new("restore")?;
+    let result = deploy_files(&deployments, &mut state, &mut backup);
+    backup.finish().context("Failed to save backup")?;
+    result?;
+    state.save().context("Failed to save deployment state")?;
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use tracing::info;
+
+use crate::{
+    backup::Generation, commands::commit::auto_commit, deployment, repository::RepositoryBuilder,
+    state::State,
+};
+
+#[derive(Debug, Args)]
+pub struct RmOptions {
+    /// Files or directories on your system
+    #[clap(required = true)]
+    paths: Vec<PathBuf>,
+    /// Leave the deployed files on your system (the default)
+    #[clap(long, overrides_with = "delete_system")]
+    keep_system: bool,
+    /// Also delete the deployed files from your system
+    #[clap(long, overrides_with = "keep_system")]
+    delete_system: bool,
+    /// Print what would be removed, without removing anything.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+pub fn rm(repo_builder: RepositoryBuilder, options: &RmOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+
+    let mut state = State::load(repository.path())?;
+    let mut backup = Generation::new("rm")?;
+    let mut sources = vec![];
+    let delete_system = options.delete_system && !options.keep_system;
+
+    for path in &options.paths {
+        // The file might already be deleted from the system.
+        let path = crate::absolute_path(path)?;
+        let tracked = deployment::tracked(&repository, &path, &plugin_map, &vars)?;
+        let path_sources = tracked.sources;
+        let deployments = tracked.deployments;
+
+        for source in &path_sources {
+            println!("{:<10} {}", "forget", source.display());
+        }
+
+        for deployment in &deployments {
+            if !deployment.dest.exists() && !deployment.dest.is_symlink() {
+                continue;
+            }
+            if delete_system {
+                println!("{:<10} {}", "remove", deployment.dest.display());
+                if options.dry_run {
+                    continue;
+                }
+                backup
+                    .save(&deployment.dest)
+                    .wrap_err("Failed to back up file")?;
+                std::fs::remove_file(&deployment.dest)
+                    .wrap_err(format!("Failed to remove '{}'", deployment.dest.display()))?;
+            } else if deployment.dest.is_symlink()
+                && same_file::is_same_file(&deployment.source, &deployment.dest)?
+            {
+                // The link would break once the file is removed from the repository.
+                println!("{:<10} {}", "unlink", deployment.dest.display());
+                if options.dry_run {
+                    continue;
+                }
+                let contents = std::fs::read(&deployment.dest)?;
+                std::fs::remove_file(&deployment.dest)?;
+                std::fs::write(&deployment.dest, contents).wrap_err(format!(
+                    "Failed to write to '{}'",
+                    deployment.dest.display()
+                ))?;
+            }
+        }
+
+        // Stop tracking the files, so `fig gc` does not remove them later.
+        if let Some(state) = &mut state {
+            state.files.retain(|file| !file.dest.starts_with(&path));
+        }
+
+        sources.extend(path_sources);
+    }
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    for source in &sources {
+        if source.is_dir() {
+            crate::remove_dir_all!(source)
+        } else {
+            std::fs::remove_file(source)
+        }
+        .wrap_err(format!("Failed to remove '{}'", source.display()))?;
+        info!(path = %source.display(), "Removed from repository");
+    }
+    repository
+        .remove_from_index(&sources)
+        .context("Failed to remove files from git")?;
+
+    backup.finish().context("Failed to save backup")?;
+    if let Some(state) = state {
+        state.save().context("Failed to save deployment state")?;
+    }
+    auto_commit(&repository, "Remove", &sources)?;
+
+    Ok(())
+}
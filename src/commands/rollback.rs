@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+use tracing::info;
+
+use crate::backup::Generation;
+
+#[derive(Debug, Args)]
+pub struct RollbackOptions {
+    /// Only restore these files
+    paths: Vec<PathBuf>,
+    /// Backup generation to restore, defaults to the last deploy
+    #[clap(short, long)]
+    generation: Option<u32>,
+    /// List all backup generations, only the most recent 20 are kept
+    #[clap(long)]
+    list: bool,
+}
+
+pub fn rollback(options: &RollbackOptions) -> Result<()> {
+    if options.list {
+        for generation in Generation::list()? {
+            println!(
+                "{:>4}  {}  {:<10} {} files",
+                generation.id,
+                humantime::format_rfc3339_seconds(generation.created_at()),
+                generation.command,
+                generation.files.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let generation = match options.generation {
+        Some(id) => Generation::load(id)?,
+        None => Generation::latest("deploy")?,
+    };
+    info!("Rolling back generation {}", generation.id);
+
+    let paths = options
+        .paths
+        .iter()
+        .map(|path| crate::absolute_path(path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    // Back up the files again, so the rollback can be undone.
+    let mut backup = Generation::new("rollback")?;
+    let restored = generation.restore(&paths, &mut backup);
+    backup.finish().context("Failed to save backup")?;
+    let restored = restored.context("Failed to restore files")?;
+
+    if restored.is_empty() {
+        println!("Nothing to restore");
+        return Ok(());
+    }
+    for path in &restored {
+        println!("restored   {}", path.display());
+    }
+    println!(
+        "Restored {} files from generation {} (undo with `fig rollback --generation {}`)",
+        restored.len(),
+        generation.id,
+        backup.id
+    );
+
+    Ok(())
+}
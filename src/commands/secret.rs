@@ -0,0 +1,166 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{bail, ensure, eyre, Context};
+use color_eyre::Result;
+use tracing::info;
+
+use crate::{
+    commands::commit::auto_commit,
+    deployment::{self, Transform},
+    facts::Facts,
+    repository::{Repository, RepositoryBuilder},
+    secret::{self, RECIPIENTS_FILE},
+};
+
+#[derive(Debug, Args)]
+pub struct SecretOptions {
+    #[clap(subcommand)]
+    pub subcommand: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Decrypt a secret, open it in $EDITOR, and encrypt it again.
+    Edit {
+        /// The encrypted file in the repository, or where it is deployed to
+        path: PathBuf,
+    },
+    /// Create an identity for this machine, and add it to the recipients of the repository.
+    Keygen,
+    /// Encrypt every secret again, e.g. after adding a machine to the recipients.
+    Rekey,
+}
+
+pub fn secret_cli(repo_builder: RepositoryBuilder, options: &SecretOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+
+    match &options.subcommand {
+        Command::Edit { path } => edit(&repository, path),
+        Command::Keygen => {
+            let identity_path = secret::identity_path(repository.path())?;
+            ensure!(
+                !identity_path.exists(),
+                "An identity already exists at '{}'",
+                identity_path.display()
+            );
+            let recipient =
+                secret::generate_identity(&identity_path).context("Failed to create identity")?;
+            info!(path = %identity_path.display(), "Created identity");
+
+            let recipients_path = repository.path().join(RECIPIENTS_FILE);
+            let mut recipients = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&recipients_path)
+                .wrap_err(format!("Failed to open '{}'", recipients_path.display()))?;
+            let facts = Facts::get();
+            writeln!(recipients, "# {}@{}", facts.username, facts.hostname)?;
+            writeln!(recipients, "{recipient}")?;
+
+            println!("Created identity: {}", identity_path.display());
+            println!("Public key: {recipient}");
+            auto_commit(&repository, "Add recipient", &[recipients_path])?;
+            println!(
+                "Commit {RECIPIENTS_FILE}, then run `fig secret rekey` on a machine that can already decrypt the secrets"
+            );
+            Ok(())
+        }
+        Command::Rekey => {
+            let mut rekeyed = vec![];
+            for namespace in repository.namespaces()? {
+                for file in namespace.source_files()? {
+                    if file.extension().is_none_or(|ext| ext != secret::EXTENSION) {
+                        continue;
+                    }
+                    let path = namespace.location.join(file);
+                    let plaintext = secret::decrypt(repository.path(), &std::fs::read(&path)?)
+                        .wrap_err(format!("Failed to decrypt '{}'", path.display()))?;
+                    std::fs::write(&path, secret::encrypt(repository.path(), &plaintext)?)?;
+                    rekeyed.push(path);
+                }
+            }
+            println!("Encrypted {} secrets again", rekeyed.len());
+            auto_commit(&repository, "Rekey", &rekeyed)?;
+            Ok(())
+        }
+    }
+}
+
+fn edit(repository: &Repository, path: &Path) -> Result<()> {
+    let source = find_secret(repository, path)?;
+    let plaintext = secret::decrypt(repository.path(), &std::fs::read(&source)?)
+        .wrap_err(format!("Failed to decrypt '{}'", source.display()))?;
+
+    // Keep the name without `.age`, so the editor can tell what kind of file it is.
+    // The directory is only readable by the current user.
+    let dir = tempfile::tempdir().context("Failed to create temporary directory")?;
+    let temp_path = dir.path().join(source.file_stem().unwrap());
+    std::fs::write(&temp_path, &plaintext)?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| {
+            if cfg!(windows) {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+    let mut args = editor.split_whitespace();
+    let program = args.next().ok_or_else(|| eyre!("$EDITOR is empty"))?;
+    let status = std::process::Command::new(program)
+        .args(args)
+        .arg(&temp_path)
+        .status()
+        .wrap_err(format!("Failed to run editor '{editor}'"))?;
+    ensure!(status.success(), "Editor '{editor}' failed, {status}");
+
+    let edited = std::fs::read(&temp_path)?;
+    if edited == plaintext {
+        // Encrypting again would change the file, even though nothing did.
+        println!("No changes made to {}", source.display());
+        return Ok(());
+    }
+
+    std::fs::write(&source, secret::encrypt(repository.path(), &edited)?)
+        .wrap_err(format!("Failed to write to '{}'", source.display()))?;
+    info!(path = %source.display(), "Edited secret");
+    println!("Updated {}", source.display());
+    auto_commit(repository, "Edit", &[source])?;
+
+    Ok(())
+}
+
+/// The encrypted file in the repository that `path` refers to.
+fn find_secret(repository: &Repository, path: &Path) -> Result<PathBuf> {
+    let path = crate::absolute_path(path)?;
+
+    if path.starts_with(repository.path().canonicalize()?) {
+        ensure!(
+            path.extension().is_some_and(|ext| ext == secret::EXTENSION),
+            "'{}' is not an encrypted file",
+            path.display()
+        );
+        return Ok(path);
+    }
+
+    let namespaces = repository.namespaces()?;
+    let plugin_map = repository.load_plugins()?;
+    let vars = repository.load_vars()?;
+    for deployment in deployment::collect(&namespaces, &plugin_map, &vars, None)? {
+        if deployment.dest == path
+            && deployment
+                .plugins
+                .iter()
+                .any(|t| matches!(t, Transform::Decrypt))
+        {
+            return Ok(deployment.source);
+        }
+    }
+    bail!(
+        "'{}' is not deployed from an encrypted file",
+        path.display()
+    )
+}
@@ -0,0 +1,48 @@
+use clap::Args;
+use color_eyre::{eyre::Context, Result};
+
+use crate::{
+    commands::{
+        commit::short,
+        deploy::{self, DeployOptions},
+        pull::pull_and_report,
+    },
+    facts::Facts,
+    repository::RepositoryBuilder,
+};
+
+#[derive(Debug, Args)]
+pub struct SyncOptions {
+    /// The message to commit local changes with
+    #[clap(short, long)]
+    message: Option<String>,
+    /// Deploy the files once they are synced
+    #[clap(long)]
+    deploy: bool,
+}
+
+pub fn sync(repo_builder: RepositoryBuilder, options: &SyncOptions) -> Result<()> {
+    let repository = repo_builder.open()?;
+
+    // Commit before pulling, so that local changes can be rebased onto the remote ones.
+    let message = options
+        .message
+        .clone()
+        .unwrap_or_else(|| format!("Sync from {}", Facts::get().hostname));
+    if let Some(oid) = repository
+        .commit(&message)
+        .context("Failed to commit changes")?
+    {
+        println!("Committed {}: {message}", short(oid));
+    }
+
+    pull_and_report(&repository, true)?;
+    repository.push().context("Failed to push changes")?;
+    println!("Pushed changes");
+
+    if options.deploy {
+        deploy::deploy(repository.into_builder(), &DeployOptions::default())?;
+    }
+
+    Ok(())
+}
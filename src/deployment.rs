@@ -0,0 +1,490 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use tracing::{debug, error, trace};
+
+use crate::{
+    facts::Facts,
+    namespace::{determine_namespace, Namespace, Strategy},
+    plugin::{self, template, PluginInfo, PluginTriggerLookup},
+    repository::Repository,
+    secret,
+    state::{self, DeployedFile},
+};
+
+/// A single file in the repository, deployed to a single target.
+#[derive(Debug)]
+pub struct Deployment<'a> {
+    /// Name of the namespace the file belongs to.
+    pub namespace: String,
+    /// Root of the repository the file is in.
+    pub repository: PathBuf,
+    /// The target of the namespace the file is deployed to.
+    pub target: PathBuf,
+    /// Path of the file in the repository.
+    pub source: PathBuf,
+    /// Path of the file on the system.
+    pub dest: PathBuf,
+    /// Plugins the file is run through, in order.
+    pub plugins: Vec<Transform<'a>>,
+    /// Variables from vars.toml, for templates.
+    pub vars: &'a toml::Table,
+    /// How the file is put on the system.
+    pub strategy: Strategy,
+    /// The file is copied instead of linked, because it is run through plugins.
+    pub copy_fallback: bool,
+}
+
+/// Something a file is run through before it is deployed.
+#[derive(Debug, Clone, Copy)]
+pub enum Transform<'a> {
+    /// The built-in template engine, for `.tmpl` files.
+    Template,
+    /// Decryption of `.age` files.
+    Decrypt,
+    /// A plugin from plugins.toml.
+    Plugin(&'a PluginInfo),
+}
+
+impl Transform<'_> {
+    /// How the transform is recorded in the deployment state.
+    pub fn label(&self) -> String {
+        match self {
+            Transform::Template => template::NAME.to_string(),
+            Transform::Decrypt => secret::NAME.to_string(),
+            Transform::Plugin(plugin) => plugin.cmd.clone(),
+        }
+    }
+}
+
+/// What deploying a file will do to the system.
+#[derive(Debug)]
+pub enum Action {
+    /// The destination does not exist yet.
+    Create(Vec<u8>),
+    /// The destination exists, and its contents will be replaced.
+    Overwrite(Vec<u8>),
+    /// The destination already has the right contents.
+    Unchanged,
+    /// The file can not be deployed, with the reason why.
+    Skip(String),
+}
+
+impl Action {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::Create(_) => "create",
+            Action::Overwrite(_) => "overwrite",
+            Action::Unchanged => "unchanged",
+            Action::Skip(_) => "skip",
+        }
+    }
+}
+
+impl<'a> Deployment<'a> {
+    /// Read the file from the repository, and run it through its plugins.
+    pub fn render(&self) -> Result<Vec<u8>, plugin::Error> {
+        let mut contents = std::fs::read(&self.source)?;
+        for transform in &self.plugins {
+            contents = match transform {
+                Transform::Template => template::render(
+                    &self.source.display().to_string(),
+                    contents,
+                    &self.template_context(),
+                )?,
+                Transform::Decrypt => secret::decrypt(&self.repository, &contents)?,
+                Transform::Plugin(plugin) => {
+                    plugin::call_on_file(plugin, &self.invocation(), contents)?
+                }
+            };
+        }
+        Ok(contents)
+    }
+
+    fn invocation(&self) -> plugin::Invocation<'_> {
+        plugin::Invocation {
+            repository: &self.repository,
+            vars: self.vars,
+            file: Some(plugin::FileInvocation {
+                source: &self.source,
+                dest: &self.dest,
+                namespace: &self.namespace,
+                target: &self.target,
+            }),
+        }
+    }
+
+    fn template_context(&self) -> template::Context<'_> {
+        let facts = Facts::get();
+        template::Context {
+            hostname: &facts.hostname,
+            os: &facts.os,
+            family: &facts.family,
+            arch: &facts.arch,
+            user: &facts.username,
+            env: std::env::vars().collect(),
+            namespace: &self.namespace,
+            target: self.target.display().to_string(),
+            vars: self.vars,
+        }
+    }
+
+    /// Work out what deploying this file would do, without touching the system.
+    pub fn plan(&self) -> Action {
+        let contents = match self.render() {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!(%err, "Failed to render '{}'", self.source.display());
+                return Action::Skip(err.to_string());
+            }
+        };
+
+        if !self.dest.exists() && !self.dest.is_symlink() {
+            return Action::Create(contents);
+        }
+        match self.is_deployed(&contents) {
+            Ok(true) => Action::Unchanged,
+            Ok(false) => Action::Overwrite(contents),
+            Err(err) => Action::Skip(format!("Failed to read destination: {err}")),
+        }
+    }
+
+    fn is_deployed(&self, contents: &[u8]) -> std::io::Result<bool> {
+        let linked = self.links_to_source();
+        Ok(match self.strategy {
+            Strategy::Copy => !linked && std::fs::read(&self.dest)? == contents,
+            Strategy::Symlink => {
+                self.dest.is_symlink()
+                    && std::fs::read_link(&self.dest)? == self.source.canonicalize()?
+            }
+            Strategy::Hardlink => !self.dest.is_symlink() && linked,
+        })
+    }
+
+    /// Whether the destination is a symlink or hard link to the file in the repository.
+    fn links_to_source(&self) -> bool {
+        same_file::is_same_file(&self.source, &self.dest).unwrap_or(false)
+    }
+
+    /// Carry out a planned action.
+    pub fn apply(&self, action: &Action) -> Result<()> {
+        let contents = match action {
+            Action::Create(contents) | Action::Overwrite(contents) => contents,
+            Action::Unchanged | Action::Skip(_) => return Ok(()),
+        };
+
+        // Make sure dest directory exists
+        if let Some(parent) = self.dest.parent() {
+            if !parent.exists() {
+                crate::create_dir_all!(parent)?;
+            }
+        }
+
+        // Replace links instead of writing through them into the repository.
+        let replace = match self.strategy {
+            Strategy::Copy => self.links_to_source(),
+            Strategy::Symlink | Strategy::Hardlink => self.dest.exists() || self.dest.is_symlink(),
+        };
+        if replace {
+            std::fs::remove_file(&self.dest)
+                .wrap_err(format!("Failed to remove '{}'", self.dest.display()))?;
+        }
+
+        match self.strategy {
+            // Decrypted secrets are only readable by the current user.
+            Strategy::Copy if self.plugins.iter().any(|t| matches!(t, Transform::Decrypt)) => {
+                write_private(&self.dest, contents)
+            }
+            Strategy::Copy => std::fs::write(&self.dest, contents),
+            Strategy::Symlink => symlink_file(&self.source.canonicalize()?, &self.dest),
+            Strategy::Hardlink => std::fs::hard_link(&self.source, &self.dest),
+        }
+        .wrap_err(format!("Failed to write to '{}'", self.dest.display()))
+    }
+
+    /// What to remember about the file once it is deployed.
+    pub fn record(&self, created: bool) -> std::io::Result<DeployedFile> {
+        Ok(DeployedFile {
+            dest: self.dest.clone(),
+            source: self.source.clone(),
+            namespace: self.namespace.clone(),
+            plugins: self.plugins.iter().map(|p| p.label()).collect(),
+            source_hash: state::hash(&std::fs::read(&self.source)?),
+            hash: state::hash(&std::fs::read(&self.dest)?),
+            created,
+        })
+    }
+}
+
+/// The files in the repository behind a file or directory on the system.
+pub struct Tracked<'a> {
+    pub namespace: Namespace,
+    /// Files or directories in the repository.
+    pub sources: Vec<PathBuf>,
+    /// Every deployment to somewhere under the path.
+    pub deployments: Vec<Deployment<'a>>,
+}
+
+/// Find the files in the repository that are deployed to `path`, which is absolute.
+pub fn tracked<'a>(
+    repository: &Repository,
+    path: &Path,
+    plugin_map: &PluginTriggerLookup<'a>,
+    vars: &'a toml::Table,
+) -> Result<Tracked<'a>> {
+    let namespace = determine_namespace(repository, path)?;
+
+    let deployments = collect(std::slice::from_ref(&namespace), plugin_map, vars, None)?
+        .into_iter()
+        .filter(|deployment| deployment.dest.starts_with(path))
+        .collect::<Vec<_>>();
+
+    let direct_source = namespace
+        .targets
+        .iter()
+        .find_map(|target| path.strip_prefix(target).ok())
+        .map(|relative| namespace.location.join(relative))
+        .filter(|source| source.exists());
+
+    // Directories are used whole, including files that are not deployed.
+    // Files are found through their deployment, as their name might have extensions for plugins.
+    let sources = match direct_source {
+        Some(source) if source.is_dir() => vec![source],
+        _ if !deployments.is_empty() => {
+            let mut sources = deployments
+                .iter()
+                .map(|deployment| deployment.source.clone())
+                .collect::<Vec<_>>();
+            sources.dedup();
+            sources
+        }
+        // Files that are not deployed, e.g. in a disabled namespace.
+        Some(source) => vec![source],
+        None => bail!("'{}' is not in the repository", path.display()),
+    };
+
+    Ok(Tracked {
+        namespace,
+        sources,
+        deployments,
+    })
+}
+
+/// Find the files in the commit `id` that were deployed to `path`, which is absolute,
+/// for files that are not in the repository anymore, e.g. after `fig rm`.
+///
+/// The name of a file might have extensions for plugins, which are not part of `path`.
+pub fn removed_sources(
+    repository: &Repository,
+    path: &Path,
+    id: git2::Oid,
+) -> Result<Vec<PathBuf>> {
+    let namespace = determine_namespace(repository, path)?;
+
+    let mut sources = vec![];
+    for target in &namespace.targets {
+        let Ok(relative) = path.strip_prefix(target) else {
+            continue;
+        };
+        let source = namespace.location.join(relative);
+        if repository.files_at(id, &source).is_ok() {
+            sources.push(source);
+            continue;
+        }
+
+        let Some(parent) = source.parent() else {
+            continue;
+        };
+        let Ok(siblings) = repository.files_at(id, parent) else {
+            continue;
+        };
+        sources.extend(
+            siblings
+                .into_iter()
+                .map(|(file, _)| file)
+                .filter(|file| file.with_extension("") == source),
+        );
+    }
+    sources.dedup();
+    Ok(sources)
+}
+
+/// Collect every file in the given namespaces, along with where it is deployed to.
+///
+/// `strategy` overrides the strategy of every namespace.
+pub fn collect<'a>(
+    namespaces: &[Namespace],
+    plugin_map: &PluginTriggerLookup<'a>,
+    vars: &'a toml::Table,
+    strategy: Option<Strategy>,
+) -> Result<Vec<Deployment<'a>>> {
+    let mut deployments = vec![];
+    for namespace in namespaces {
+        if !namespace.enabled {
+            debug!("Skipping disabled namespace '{}'", namespace.name());
+            continue;
+        }
+        if let Some(error) = &namespace.error {
+            bail!("{error}");
+        }
+        // Namespaces are directories in the root of the repository.
+        let repository = namespace
+            .location
+            .parent()
+            .unwrap_or(&namespace.location)
+            .to_path_buf();
+        for file in namespace.source_files()? {
+            let source = namespace.location.join(&file);
+
+            // Each extension with plugins is trimmed from the file name.
+            let mut plugins = vec![];
+            let mut file_name = file.clone();
+            loop {
+                let transforms = file_name
+                    .extension()
+                    .map(|ext| transforms_for(ext.to_str().unwrap(), plugin_map, namespace))
+                    .unwrap_or_default();
+                if transforms.is_empty() {
+                    break;
+                }
+                plugins.extend(transforms);
+                file_name = file_name.with_extension("");
+            }
+            // Plugins for paths run last, on the contents of the file as it will be deployed.
+            for plugin in plugin_map.for_path(namespace.name(), &file, &file_name) {
+                let added = plugins
+                    .iter()
+                    .any(|t| matches!(t, Transform::Plugin(p) if p.name == plugin.name));
+                if !added && namespace.plugins.allows(&plugin.name) {
+                    plugins.push(Transform::Plugin(plugin));
+                }
+            }
+            // A pipeline decides what the file is run through, but not the name it is deployed as.
+            if let Some(pipeline) = plugin_map.pipeline_for(namespace.name(), &file, &file_name) {
+                plugins = pipeline
+                    .steps
+                    .iter()
+                    .filter(|transform| is_allowed(transform, namespace))
+                    .cloned()
+                    .collect();
+            }
+
+            // Plugin output only exists in memory, so it can't be linked to.
+            let strategy = strategy.unwrap_or(namespace.strategy);
+            let copy_fallback = !plugins.is_empty() && strategy != Strategy::Copy;
+
+            for target in &namespace.targets {
+                let dest = target.join(&file_name);
+                trace!(
+                    "Found file '{}' deploying to '{}'",
+                    source.display(),
+                    dest.display()
+                );
+                deployments.push(Deployment {
+                    namespace: namespace.name().to_string(),
+                    repository: repository.clone(),
+                    target: target.clone(),
+                    source: source.clone(),
+                    dest,
+                    plugins: plugins.clone(),
+                    vars,
+                    strategy: if copy_fallback {
+                        Strategy::Copy
+                    } else {
+                        strategy
+                    },
+                    copy_fallback,
+                });
+            }
+        }
+    }
+
+    debug!("Found {} files to deploy", deployments.len());
+
+    Ok(deployments)
+}
+
+/// Everywhere the files in the repository are deployed to on any machine, including from disabled
+/// namespaces and to targets that are not for this machine. A deployed file that is not in here
+/// was removed from the repository, or moved.
+pub fn all_dests<'a>(
+    namespaces: &[Namespace],
+    plugin_map: &PluginTriggerLookup<'a>,
+    vars: &'a toml::Table,
+) -> Result<Vec<PathBuf>> {
+    let namespaces = namespaces
+        .iter()
+        .map(|namespace| {
+            let mut namespace = namespace.clone();
+            namespace.enabled = true;
+            namespace
+                .targets
+                .extend(std::mem::take(&mut namespace.inactive_targets));
+            namespace
+        })
+        .collect::<Vec<_>>();
+    Ok(collect(&namespaces, plugin_map, vars, None)?
+        .into_iter()
+        .map(|deployment| deployment.dest)
+        .collect())
+}
+
+/// What a file with the extension `ext` is run through, if anything.
+/// Plugins in plugins.toml take precedence over the built-in transforms.
+fn transforms_for<'a>(
+    ext: &str,
+    plugin_map: &PluginTriggerLookup<'a>,
+    namespace: &Namespace,
+) -> Vec<Transform<'a>> {
+    let plugins = plugin_map.for_extension(namespace.name(), ext);
+    let transforms = if !plugins.is_empty() {
+        plugins.into_iter().map(Transform::Plugin).collect()
+    } else if ext == template::EXTENSION {
+        vec![Transform::Template]
+    } else if ext == secret::EXTENSION {
+        vec![Transform::Decrypt]
+    } else {
+        vec![]
+    };
+    transforms
+        .into_iter()
+        .filter(|transform| is_allowed(transform, namespace))
+        .collect()
+}
+
+/// Whether `namespace` runs its files through `transform`.
+fn is_allowed(transform: &Transform, namespace: &Namespace) -> bool {
+    match transform {
+        Transform::Template => namespace.plugins.allows(template::NAME),
+        Transform::Decrypt => namespace.plugins.allows(secret::NAME),
+        Transform::Plugin(plugin) => namespace.plugins.allows(&plugin.name),
+    }
+}
+
+/// Write a file that only the current user can read, even if it already existed.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+#[cfg(unix)]
+fn symlink_file(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
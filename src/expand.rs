@@ -0,0 +1,237 @@
+use std::path::{Path, PathBuf};
+
+use directories::{BaseDirs, ProjectDirs, UserDirs};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Environment variable '{}' is not set", .0)]
+    UnsetVariable(String),
+    #[error("Unknown directory '{{{{{}}}}}'", .0)]
+    UnknownDirectory(String),
+    #[error("Directory '{{{{{}}}}}' does not exist on this system", .0)]
+    MissingDirectory(String),
+    #[error("Missing closing '{}' in '{}'", .close, .text)]
+    Unclosed { close: &'static str, text: String },
+    #[error("Path is not valid unicode: '{}'", .0.display())]
+    InvalidUnicode(PathBuf),
+}
+
+/// Expand a path, see [`expand`].
+pub fn expand_path(path: &Path) -> Result<PathBuf, Error> {
+    let text = path
+        .to_str()
+        .ok_or_else(|| Error::InvalidUnicode(path.to_path_buf()))?;
+    expand(text).map(PathBuf::from)
+}
+
+/// Expand variables in `text`:
+/// - `~` at the start, to the home directory.
+/// - `$VAR`, `${VAR}` and `%VAR%`, to environment variables. `$$` is a literal `$`.
+/// - `{{name}}`, to a directory from [`directories`], e.g. `{{config_dir}}` or `{{download_dir}}`.
+///   Project directories take the name of the project, e.g. `{{project_config_dir:nvim}}`
+///   or `{{project_config_dir:com.Company.App}}`.
+pub fn expand(text: &str) -> Result<String, Error> {
+    let mut out = String::new();
+    let mut rest = text;
+
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(['/', '\\']) {
+            out.push_str(&directory("home_dir")?);
+            rest = after;
+        }
+    }
+
+    while let Some(i) = rest.find(['$', '%', '{']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let (name, after) = until(after, "}", text)?;
+            out.push_str(&variable(name)?);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('$') {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            if len == 0 {
+                out.push('$');
+            } else {
+                out.push_str(&variable(&after[..len])?);
+            }
+            rest = &after[len..];
+        } else if let Some(after) = rest.strip_prefix("{{") {
+            let (name, after) = until(after, "}}", text)?;
+            out.push_str(&directory(name.trim())?);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('%') {
+            // A lone `%` is just part of the path.
+            match after.find('%') {
+                Some(len) if len > 0 && !after[..len].contains(['/', '\\']) => {
+                    out.push_str(&variable(&after[..len])?);
+                    rest = &after[len + 1..];
+                }
+                _ => {
+                    out.push('%');
+                    rest = after;
+                }
+            }
+        } else {
+            // A single `{`
+            out.push('{');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+fn until<'a>(
+    text: &'a str,
+    close: &'static str,
+    full_text: &str,
+) -> Result<(&'a str, &'a str), Error> {
+    match text.find(close) {
+        Some(i) => Ok((&text[..i], &text[i + close.len()..])),
+        None => Err(Error::Unclosed {
+            close,
+            text: full_text.to_string(),
+        }),
+    }
+}
+
+fn variable(name: &str) -> Result<String, Error> {
+    std::env::var(name).map_err(|_| Error::UnsetVariable(name.to_string()))
+}
+
+fn directory(name: &str) -> Result<String, Error> {
+    let missing = || Error::MissingDirectory(name.to_string());
+
+    if let Some((kind, project)) = name.split_once(':') {
+        let project = project.trim();
+        let (qualifier, organization, application) =
+            match project.splitn(3, '.').collect::<Vec<_>>()[..] {
+                [qualifier, organization, application] => (qualifier, organization, application),
+                _ => ("", "", project),
+            };
+        let dirs = ProjectDirs::from(qualifier, organization, application).ok_or_else(missing)?;
+        let path = match kind.trim() {
+            "project_cache_dir" => Some(dirs.cache_dir()),
+            "project_config_dir" => Some(dirs.config_dir()),
+            "project_config_local_dir" => Some(dirs.config_local_dir()),
+            "project_data_dir" => Some(dirs.data_dir()),
+            "project_data_local_dir" => Some(dirs.data_local_dir()),
+            "project_preference_dir" => Some(dirs.preference_dir()),
+            "project_runtime_dir" => dirs.runtime_dir(),
+            "project_state_dir" => dirs.state_dir(),
+            _ => return Err(Error::UnknownDirectory(name.to_string())),
+        };
+        return path.map(display).ok_or_else(missing);
+    }
+
+    let base_dirs = BaseDirs::new().ok_or_else(missing)?;
+    let path = match name {
+        "home" | "home_dir" => Some(base_dirs.home_dir()),
+        "cache_dir" => Some(base_dirs.cache_dir()),
+        "config_dir" => Some(base_dirs.config_dir()),
+        "config_local_dir" => Some(base_dirs.config_local_dir()),
+        "data_dir" => Some(base_dirs.data_dir()),
+        "data_local_dir" => Some(base_dirs.data_local_dir()),
+        "executable_dir" => base_dirs.executable_dir(),
+        "preference_dir" => Some(base_dirs.preference_dir()),
+        "runtime_dir" => base_dirs.runtime_dir(),
+        "state_dir" => base_dirs.state_dir(),
+        _ => {
+            let user_dirs = UserDirs::new().ok_or_else(missing)?;
+            return match name {
+                "audio_dir" => user_dirs.audio_dir(),
+                "desktop_dir" => user_dirs.desktop_dir(),
+                "document_dir" => user_dirs.document_dir(),
+                "download_dir" => user_dirs.download_dir(),
+                "font_dir" => user_dirs.font_dir(),
+                "picture_dir" => user_dirs.picture_dir(),
+                "public_dir" => user_dirs.public_dir(),
+                "template_dir" => user_dirs.template_dir(),
+                "video_dir" => user_dirs.video_dir(),
+                _ => return Err(Error::UnknownDirectory(name.to_string())),
+            }
+            .map(display)
+            .ok_or_else(missing);
+        }
+    };
+    path.map(display).ok_or_else(missing)
+}
+
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home() -> String {
+        display(BaseDirs::new().unwrap().home_dir())
+    }
+
+    #[test]
+    fn variables() {
+        std::env::set_var("FIG_TEST_EXPAND", "value");
+        assert_eq!(expand("$FIG_TEST_EXPAND/a").unwrap(), "value/a");
+        assert_eq!(expand("${FIG_TEST_EXPAND}a").unwrap(), "valuea");
+        assert_eq!(expand("%FIG_TEST_EXPAND%\\a").unwrap(), "value\\a");
+        assert_eq!(expand("$$FIG_TEST_EXPAND").unwrap(), "$FIG_TEST_EXPAND");
+    }
+
+    #[test]
+    fn unset_variable() {
+        for text in ["$FIG_TEST_UNSET", "${FIG_TEST_UNSET}", "%FIG_TEST_UNSET%"] {
+            assert!(
+                matches!(expand(text), Err(Error::UnsetVariable(name)) if name == "FIG_TEST_UNSET"),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn unclosed() {
+        assert!(matches!(
+            expand("${FIG_TEST_UNSET/a"),
+            Err(Error::Unclosed { close: "}", .. })
+        ));
+        assert!(matches!(
+            expand("{{home_dir/a"),
+            Err(Error::Unclosed { close: "}}", .. })
+        ));
+    }
+
+    #[test]
+    fn literal_characters() {
+        assert_eq!(expand("/a/50%/b").unwrap(), "/a/50%/b");
+        assert_eq!(expand("/a/50%/b%").unwrap(), "/a/50%/b%");
+        assert_eq!(expand("/a/$/b$").unwrap(), "/a/$/b$");
+        assert_eq!(expand("/a/{b}").unwrap(), "/a/{b}");
+    }
+
+    #[test]
+    fn home_directory() {
+        assert_eq!(expand("~").unwrap(), home());
+        assert_eq!(expand("~/a").unwrap(), format!("{}/a", home()));
+        assert_eq!(expand("{{ home_dir }}/a").unwrap(), format!("{}/a", home()));
+        // Other users' homes are not expanded.
+        assert_eq!(expand("~user/a").unwrap(), "~user/a");
+        assert_eq!(expand("/a/~").unwrap(), "/a/~");
+    }
+
+    #[test]
+    fn unknown_directory() {
+        assert!(matches!(
+            expand("{{nope}}"),
+            Err(Error::UnknownDirectory(name)) if name == "nope"
+        ));
+    }
+}
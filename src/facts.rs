@@ -0,0 +1,30 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+/// Information about the machine fig is running on.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Facts {
+    /// Operating system, as in [`std::env::consts::OS`], e.g. "linux" or "windows".
+    pub os: String,
+    /// Operating system family, "unix" or "windows".
+    pub family: String,
+    /// CPU architecture, as in [`std::env::consts::ARCH`], e.g. "x86_64".
+    pub arch: String,
+    pub hostname: String,
+    pub username: String,
+}
+
+impl Facts {
+    /// Facts about the current machine, only gathered once.
+    pub fn get() -> &'static Facts {
+        static FACTS: OnceLock<Facts> = OnceLock::new();
+        FACTS.get_or_init(|| Facts {
+            os: std::env::consts::OS.to_string(),
+            family: std::env::consts::FAMILY.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            hostname: whoami::fallible::hostname().unwrap_or_default(),
+            username: whoami::username(),
+        })
+    }
+}
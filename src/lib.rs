@@ -1,13 +1,62 @@
+use std::path::{Path, PathBuf};
+
 use directories::ProjectDirs;
 
+pub mod backup;
 pub mod commands;
+pub mod deployment;
+pub mod expand;
+pub mod facts;
 mod log_utils;
 pub mod namespace;
 pub mod plugin;
 pub mod repository;
+pub mod secret;
+pub mod settings;
+pub mod state;
 pub mod template;
+pub mod vars;
+
+/// Make `path` absolute, resolving links in the parts of it that exist.
+/// Unlike [`Path::canonicalize`], the file does not have to exist, e.g. because it was removed.
+pub fn absolute_path(path: &Path) -> std::io::Result<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Ok(path);
+    }
+    let path = std::path::absolute(path)?;
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(absolute_path(parent)?.join(name)),
+        _ => Ok(path),
+    }
+}
 
 pub fn project_dirs() -> ProjectDirs {
     ProjectDirs::from("", "", "fig")
         .expect("Failed to find home directory, maybe your operating system is unsupported?")
 }
+
+/// Where fig keeps track of what it has deployed, and backs up files.
+pub fn state_dir() -> PathBuf {
+    let project_dirs = project_dirs();
+    match project_dirs.state_dir() {
+        Some(dir) => dir.to_path_buf(),
+        None => outside_repository(&project_dirs.data_local_dir().join("state"), "state"),
+    }
+}
+
+/// Where the files of this machine are kept, such as its identity.
+pub fn config_dir() -> PathBuf {
+    outside_repository(project_dirs().config_dir(), "config")
+}
+
+/// `dir`, unless it is in the default repository, as it is on platforms where the data, config and
+/// state directories are the same. Then a directory next to the repository, e.g. `fig-state`.
+fn outside_repository(dir: &Path, name: &str) -> PathBuf {
+    let project_dirs = project_dirs();
+    let repository = project_dirs.data_dir();
+    if !dir.starts_with(repository) {
+        return dir.to_path_buf();
+    }
+    let file_name = repository.file_name().unwrap_or_default().to_string_lossy();
+    repository.with_file_name(format!("{file_name}-{name}"))
+}
@@ -1,6 +1,6 @@
 use std::{fs::File, path::PathBuf};
 
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use color_eyre::{eyre::Context, Result};
 use tracing::{level_filters::LevelFilter, Level};
 use tracing_subscriber::{fmt, fmt::writer::MakeWriterExt, layer::SubscriberExt, Registry};
@@ -9,8 +9,11 @@ use fig::repository::RepositoryBuilder;
 pub use fig::*;
 
 use crate::commands::{
-    add::AddOptions, clone::CloneOptions, cmd::CmdOptions, deploy::DeployOptions,
-    info::InfoOptions, init::InitOptions, list::ListOptions, namespace::NamespaceOptions,
+    add::AddOptions, capture::CaptureOptions, clone::CloneOptions, cmd::CmdOptions,
+    commit::CommitOptions, deploy::DeployOptions, diff::DiffOptions, gc::GcOptions,
+    info::InfoOptions, init::InitOptions, list::ListOptions, log::LogOptions, mv::MvOptions,
+    namespace::NamespaceOptions, pull::PullOptions, restore::RestoreOptions, rm::RmOptions,
+    rollback::RollbackOptions, secret::SecretOptions, sync::SyncOptions,
 };
 
 #[derive(Debug, Parser)]
@@ -30,13 +33,22 @@ struct Cli {
 enum Command {
     /// Add a file to the configuration repository.
     Add(AddOptions),
+    /// Copy files edited on your system back into the configuration repository.
+    #[command(alias = "re-add")]
+    Capture(CaptureOptions),
     /// Clone another repository.
     Clone(CloneOptions),
     /// Run a command in the configuration repository directory.
     #[command(alias = "sh")]
     Cmd(CmdOptions),
+    /// Commit every change in the configuration repository.
+    Commit(CommitOptions),
     /// Deploy files from the configuration repository to your system.
     Deploy(DeployOptions),
+    /// Show how deploying would change the files on your system.
+    Diff(DiffOptions),
+    /// Remove deployed files that are no longer in the configuration repository.
+    Gc(GcOptions),
     /// Display information about your configuratino repository.
     #[command(alias = "status")]
     Info(InfoOptions),
@@ -44,11 +56,28 @@ enum Command {
     Init(InitOptions),
     /// Print all files that are in the configuration repository.
     List(ListOptions),
+    /// Show the commits that changed a file.
+    Log(LogOptions),
+    /// Move a file to another place on your system, and in the configuration repository.
+    Mv(MvOptions),
     /// Manage your namespaces
     #[command(alias = "ns")]
     Namespace(NamespaceOptions),
     /// Completely delete your configuration repository.
     Purge,
+    /// Get changes to the configuration repository from its remote.
+    Pull(PullOptions),
+    /// Restore a file in the configuration repository from an earlier commit.
+    Restore(RestoreOptions),
+    /// Stop tracking files, removing them from the configuration repository.
+    #[command(alias = "forget")]
+    Rm(RmOptions),
+    /// Restore files overwritten by a deploy.
+    Rollback(RollbackOptions),
+    /// Manage encrypted files.
+    Secret(SecretOptions),
+    /// Commit local changes, pull remote ones, and push them back.
+    Sync(SyncOptions),
 }
 
 fn main() -> Result<()> {
@@ -66,6 +95,7 @@ fn main() -> Result<()> {
         let file = File::options()
             .create(true)
             .write(true)
+            .truncate(false)
             .open(log_path)
             .context("Failed to open log file")?;
 
@@ -98,27 +128,63 @@ fn main() -> Result<()> {
         Command::Add(options) => {
             commands::add::add(repo_builder, options)?;
         }
+        Command::Capture(options) => {
+            commands::capture::capture(repo_builder, options)?;
+        }
         Command::Clone(options) => {
             commands::clone::clone(repo_builder, options)?;
         }
         Command::Cmd(options) => {
             commands::cmd::cmd(repo_builder, options)?;
         }
+        Command::Commit(options) => {
+            commands::commit::commit(repo_builder, options)?;
+        }
         Command::Deploy(options) => {
             commands::deploy::deploy(repo_builder, options)?;
         }
+        Command::Diff(options) => {
+            commands::diff::diff(repo_builder, options)?;
+        }
+        Command::Gc(options) => {
+            commands::gc::gc(repo_builder, options)?;
+        }
         Command::Info(options) => {
             commands::info::info(repo_builder, options)?;
         }
         Command::List(options) => {
             commands::list::list(repo_builder, options)?;
         }
+        Command::Log(options) => {
+            commands::log::log(repo_builder, options)?;
+        }
+        Command::Mv(options) => {
+            commands::mv::mv(repo_builder, options)?;
+        }
         Command::Namespace(options) => {
             commands::namespace::namespace_cli(repo_builder, options)?;
         }
         Command::Purge => {
             commands::purge::purge(repo_builder)?;
         }
+        Command::Pull(options) => {
+            commands::pull::pull(repo_builder, options)?;
+        }
+        Command::Restore(options) => {
+            commands::restore::restore(repo_builder, options)?;
+        }
+        Command::Rm(options) => {
+            commands::rm::rm(repo_builder, options)?;
+        }
+        Command::Rollback(options) => {
+            commands::rollback::rollback(options)?;
+        }
+        Command::Secret(options) => {
+            commands::secret::secret_cli(repo_builder, options)?;
+        }
+        Command::Sync(options) => {
+            commands::sync::sync(repo_builder, options)?;
+        }
         Command::Init(options) => {
             commands::init::init(repo_builder, options)?;
         }
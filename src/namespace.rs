@@ -1,45 +1,414 @@
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 
+use clap::ValueEnum;
 use color_eyre::{
     eyre::{bail, Context},
     Result,
 };
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use serde::{Deserialize, Serialize};
-use tracing::{error, instrument, trace};
+use tracing::{error, instrument, trace, warn};
 
-use crate::repository::Repository;
+use crate::{expand, facts::Facts, repository::Repository};
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The file a namespace is defined in.
+pub const CONFIG_FILE: &str = "namespace.toml";
+/// The old, line based, file a namespace was defined in.
+pub const LEGACY_CONFIG_FILE: &str = "namespace.fig";
+/// Gitignore style patterns of files that are not part of a namespace, in the root of
+/// the repository (for every namespace) or of a namespace.
+pub const IGNORE_FILE: &str = ".figignore";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Namespace {
     /// The output location, where files are deployed to.
     pub targets: Vec<PathBuf>,
+    /// Targets that are not for this machine, where they can be expanded on it.
+    /// Files deployed to these are still in the repository, so they are never pruned.
+    #[serde(skip)]
+    pub inactive_targets: Vec<PathBuf>,
     /// The physical location of the namespace, where files are stored.
     pub location: PathBuf,
+    /// Why a target could not be expanded, e.g. an unset variable.
+    /// The namespace is still listed, but it can not be deployed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// How files are put in the targets.
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Gitignore style patterns of files that are not deployed.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Disabled namespaces are not deployed.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub plugins: PluginSettings,
+}
+
+/// The contents of a namespace.toml file.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NamespaceConfig {
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub plugins: PluginSettings,
+}
+
+/// A place a namespace can be deployed to.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged, try_from = "RawTarget")]
+pub enum Target {
+    /// Always deployed to.
+    Path(PathBuf),
+    /// Only deployed to on machines that match the condition.
+    Conditional(ConditionalTarget),
+}
+
+/// A target as written in namespace.toml. Tables are parsed separately, so a misspelled
+/// condition is reported instead of making the target unconditional.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTarget {
+    Path(PathBuf),
+    Conditional(toml::Table),
+}
+
+/// A target with requirements a machine must meet, similar to `cfg` in Rust.
+/// Every field that is set must match.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConditionalTarget {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<OneOrMany>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<OneOrMany>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<OneOrMany>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<OneOrMany>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<OneOrMany>,
+    /// Environment variables, and the values they must have.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, OneOrMany>,
+}
+
+/// A value in a condition, which matches if any of its values match.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Which plugins files in a namespace are run through.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PluginSettings {
+    /// Run plugins on files in this namespace at all.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Names of plugins that are not run on this namespace.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled: Vec<String>,
+}
+
+/// How a file is deployed to the system.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Write a copy of the file.
+    #[default]
+    Copy,
+    /// Link to the file in the repository, so edits go straight into the repository.
+    Symlink,
+    /// Hard link to the file in the repository.
+    Hardlink,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for NamespaceConfig {
+    fn default() -> Self {
+        NamespaceConfig {
+            targets: vec![],
+            strategy: Strategy::default(),
+            ignore: vec![],
+            enabled: true,
+            plugins: PluginSettings::default(),
+        }
+    }
+}
+
+impl Default for PluginSettings {
+    fn default() -> Self {
+        PluginSettings {
+            enabled: true,
+            disabled: vec![],
+        }
+    }
+}
+
+impl PluginSettings {
+    /// Whether the plugin called `name` is run on the namespace.
+    pub fn allows(&self, name: &str) -> bool {
+        self.enabled && !self.disabled.iter().any(|disabled| disabled == name)
+    }
+}
+
+impl TryFrom<RawTarget> for Target {
+    type Error = toml::de::Error;
+
+    fn try_from(target: RawTarget) -> Result<Self, Self::Error> {
+        Ok(match target {
+            RawTarget::Path(path) => Target::Path(path),
+            RawTarget::Conditional(table) => {
+                Target::Conditional(toml::Value::Table(table).try_into()?)
+            }
+        })
+    }
+}
+
+impl Target {
+    pub fn path(&self) -> &Path {
+        match self {
+            Target::Path(path) => path,
+            Target::Conditional(target) => &target.path,
+        }
+    }
+
+    /// Whether the target is used on this machine.
+    pub fn is_active(&self, facts: &Facts) -> bool {
+        match self {
+            Target::Path(_) => true,
+            Target::Conditional(target) => target.matches(facts),
+        }
+    }
+}
+
+impl ConditionalTarget {
+    pub fn matches(&self, facts: &Facts) -> bool {
+        let matches = |matcher: &Option<OneOrMany>, value: &str| {
+            matcher
+                .as_ref()
+                .is_none_or(|matcher| matcher.matches(value))
+        };
+        matches(&self.os, &facts.os)
+            && matches(&self.family, &facts.family)
+            && matches(&self.arch, &facts.arch)
+            && matches(&self.hostname, &facts.hostname)
+            && matches(&self.username, &facts.username)
+            && self.env.iter().all(|(name, matcher)| {
+                std::env::var(name).is_ok_and(|value| matcher.matches(&value))
+            })
+    }
+}
+
+impl OneOrMany {
+    /// Compares case insensitively, as hostnames are often in uppercase on windows.
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            OneOrMany::One(expected) => expected.eq_ignore_ascii_case(value),
+            OneOrMany::Many(expected) => expected.iter().any(|e| e.eq_ignore_ascii_case(value)),
+        }
+    }
+}
+
+impl Strategy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Strategy::Copy => "copy",
+            Strategy::Symlink => "symlink",
+            Strategy::Hardlink => "hardlink",
+        }
+    }
+}
+
+impl NamespaceConfig {
+    /// Read the namespace definition in `dir`, if there is one.
+    pub fn read(dir: &Path) -> Result<Option<NamespaceConfig>> {
+        let path = dir.join(CONFIG_FILE);
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .wrap_err(format!("Failed to read '{}'", path.display()))?;
+            let config =
+                toml::from_str(&text).wrap_err(format!("Failed to parse '{}'", path.display()))?;
+            return Ok(Some(config));
+        }
+
+        let path = dir.join(LEGACY_CONFIG_FILE);
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .wrap_err(format!("Failed to read '{}'", path.display()))?;
+            return Ok(Some(Self::parse_legacy(&text)));
+        }
+
+        Ok(None)
+    }
+
+    /// Parse the old namespace.fig format, which has a target on each line.
+    pub fn parse_legacy(text: &str) -> NamespaceConfig {
+        NamespaceConfig {
+            targets: text
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(|line| Target::Path(PathBuf::from(line)))
+                .collect(),
+            ..NamespaceConfig::default()
+        }
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(CONFIG_FILE);
+        let text = toml::to_string_pretty(self).wrap_err("Failed to serialize namespace")?;
+        std::fs::write(&path, text).wrap_err(format!("Failed to write to '{}'", path.display()))
+    }
 }
 
 impl Namespace {
+    /// Load the namespace stored in `location`, if it has a definition.
+    pub fn load(location: PathBuf) -> Result<Option<Namespace>> {
+        let Some(config) = NamespaceConfig::read(&location)? else {
+            return Ok(None);
+        };
+
+        let mut namespace = Namespace {
+            targets: vec![],
+            inactive_targets: vec![],
+            location,
+            error: None,
+            strategy: config.strategy,
+            ignore: config.ignore,
+            enabled: config.enabled,
+            plugins: config.plugins,
+        };
+        let facts = Facts::get();
+        for target in config.targets {
+            if !target.is_active(facts) {
+                trace!(
+                    "Skipping target '{}' of namespace '{}', it is not for this machine",
+                    target.path().display(),
+                    namespace.name()
+                );
+                // Its variables might not be set on this machine.
+                if let Ok(target) = expand::expand_path(target.path()) {
+                    namespace
+                        .inactive_targets
+                        .push(target.canonicalize().unwrap_or(target));
+                }
+                continue;
+            }
+            let target = match expand::expand_path(target.path()) {
+                Ok(target) => target,
+                Err(e) => {
+                    let error = format!(
+                        "Failed to expand target '{}' of namespace '{}': {e}",
+                        target.path().display(),
+                        namespace.name()
+                    );
+                    warn!("{error}");
+                    namespace.error = Some(error);
+                    continue;
+                }
+            };
+            match target.canonicalize() {
+                Ok(target) => namespace.targets.push(target),
+                Err(_) => {
+                    // It will be created when deploying.
+                    warn!(
+                        "Target '{}' of namespace '{}' does not exist",
+                        target.display(),
+                        namespace.name()
+                    );
+                    namespace.targets.push(target);
+                }
+            }
+        }
+        Ok(Some(namespace))
+    }
+
+    /// The name of the namespace, which is the name of its directory.
+    pub fn name(&self) -> &str {
+        self.location
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+
+    /// Every file in the namespace, where it is deployed to.
     pub fn files(&self) -> Result<Vec<PathBuf>> {
         let mut files = vec![];
-        self.recurse_dir(&self.location, &mut files, 50)?;
+        for file in self.source_files()? {
+            for target in &self.targets {
+                files.push(target.join(&file));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Every file in the namespace, relative to its location.
+    pub fn source_files(&self) -> Result<Vec<PathBuf>> {
+        let ignore = self.ignore_matcher()?;
+        let mut files = vec![];
+        self.recurse_dir(&self.location, &ignore, &mut files, 50)?;
         Ok(files)
     }
 
-    fn recurse_dir(&self, dir: &Path, files: &mut Vec<PathBuf>, depth: u8) -> Result<()> {
-        assert!(depth != 0, "Overflowed depth");
+    /// Matches files that are not part of the namespace, from the `ignore` setting and
+    /// `.figignore` files. Patterns are relative to the namespace, even in the repository's
+    /// `.figignore`, and later ones take precedence.
+    pub fn ignore_matcher(&self) -> Result<Gitignore> {
+        let mut builder = GitignoreBuilder::new(&self.location);
+        if let Some(repository) = self.location.parent() {
+            add_ignore_file(&mut builder, &repository.join(IGNORE_FILE))?;
+        }
+        for pattern in &self.ignore {
+            builder.add_line(None, pattern).wrap_err(format!(
+                "Invalid ignore pattern '{pattern}' in namespace '{}'",
+                self.name()
+            ))?;
+        }
+        add_ignore_file(&mut builder, &self.location.join(IGNORE_FILE))?;
+        builder.build().wrap_err("Failed to build ignore patterns")
+    }
+
+    fn recurse_dir(
+        &self,
+        dir: &Path,
+        ignore: &Gitignore,
+        files: &mut Vec<PathBuf>,
+        depth: u8,
+    ) -> Result<()> {
+        if depth == 0 {
+            bail!("'{}' is nested too deeply", dir.display());
+        }
         for entry in dir.read_dir().wrap_err("Failed to read directory")? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_file() {
-                if path.file_name().unwrap() != "namespace.fig" {
-                    for target in &self.targets {
-                        let relative_path = path.strip_prefix(&self.location)?;
-                        let display_path = target.join(relative_path);
-                        files.push(display_path);
-                    }
+            let is_dir = path.is_dir();
+            if ignore.matched(&path, is_dir).is_ignore() {
+                trace!("Ignoring '{}'", path.display());
+                continue;
+            }
+            if !is_dir {
+                let relative_path = path.strip_prefix(&self.location)?;
+                if !is_config_file(relative_path) {
+                    files.push(relative_path.to_path_buf());
                 }
             } else {
-                self.recurse_dir(&path, files, depth - 1)?;
+                self.recurse_dir(&path, ignore, files, depth - 1)?;
             }
         }
 
@@ -47,6 +416,24 @@ impl Namespace {
     }
 }
 
+/// Add the patterns in the ignore file at `path` to `builder`, if the file exists.
+fn add_ignore_file(builder: &mut GitignoreBuilder, path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    match builder.add(path) {
+        None => Ok(()),
+        Some(err) => Err(err).wrap_err(format!("Invalid ignore file '{}'", path.display())),
+    }
+}
+
+/// Files used by fig itself, which are never deployed.
+fn is_config_file(relative_path: &Path) -> bool {
+    relative_path == Path::new(CONFIG_FILE)
+        || relative_path == Path::new(IGNORE_FILE)
+        || relative_path.extension().is_some_and(|ext| ext == "fig")
+}
+
 #[instrument(skip(repository), fields(repository = % repository.path().display()))]
 pub fn determine_namespace(
     repository: &Repository,
@@ -59,13 +446,12 @@ pub fn determine_namespace(
 
     while let Some(parent) = path.parent() {
         path = parent;
+        let parent = parent
+            .canonicalize()
+            .unwrap_or_else(|_| parent.to_path_buf());
         for ns in repository.namespaces()? {
             // Only allow the file to be added to the namespace if it is in any of the targets.
-            if ns
-                .targets
-                .iter()
-                .any(|target| target.canonicalize().ok() == parent.canonicalize().ok())
-            {
+            if ns.targets.contains(&parent) {
                 return Ok(ns);
             }
         }
@@ -74,3 +460,80 @@ pub fn determine_namespace(
     error!("'{}' has no namespace", original_path.display());
     bail!("'{}' has no namespace", original_path.display())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        Facts {
+            os: "linux".to_string(),
+            family: "unix".to_string(),
+            arch: "x86_64".to_string(),
+            hostname: "Work-Laptop".to_string(),
+            username: "me".to_string(),
+        }
+    }
+
+    fn parse_target(target: &str) -> Result<Target, toml::de::Error> {
+        let config: NamespaceConfig = toml::from_str(&format!("targets = [{target}]"))?;
+        Ok(config.targets.into_iter().next().unwrap())
+    }
+
+    fn is_active(condition: &str) -> bool {
+        parse_target(&format!("{{ path = \"/a\", {condition} }}"))
+            .unwrap()
+            .is_active(&facts())
+    }
+
+    #[test]
+    fn plain_target() {
+        let target = parse_target("\"~/.config\"").unwrap();
+        assert!(matches!(&target, Target::Path(path) if path == Path::new("~/.config")));
+        assert!(target.is_active(&facts()));
+    }
+
+    #[test]
+    fn condition_matches() {
+        assert!(is_active("os = \"linux\""));
+        assert!(!is_active("os = \"windows\""));
+        assert!(is_active("os = [\"macos\", \"linux\"]"));
+        // Every field that is set must match.
+        assert!(!is_active("os = \"linux\", arch = \"aarch64\""));
+    }
+
+    #[test]
+    fn condition_ignores_case() {
+        assert!(is_active("hostname = \"work-laptop\""));
+        assert!(is_active("family = \"UNIX\""));
+    }
+
+    #[test]
+    fn condition_env() {
+        std::env::set_var("FIG_TEST_CONDITION", "yes");
+        assert!(is_active("env = { FIG_TEST_CONDITION = \"yes\" }"));
+        assert!(!is_active("env = { FIG_TEST_CONDITION = \"no\" }"));
+        assert!(!is_active("env = { FIG_TEST_UNSET = \"yes\" }"));
+    }
+
+    #[test]
+    fn misspelled_condition() {
+        let error = parse_target("{ path = \"/a\", hostnmae = \"work-laptop\" }").unwrap_err();
+        assert!(error.to_string().contains("hostnmae"), "{error}");
+        assert!(parse_target("{ hostname = \"work-laptop\" }").is_err());
+    }
+
+    #[test]
+    fn parse_legacy_targets() {
+        let config = NamespaceConfig::parse_legacy("~/.config\n\n  /etc/app  \n");
+        let targets = config.targets.iter().map(Target::path).collect::<Vec<_>>();
+        assert_eq!(targets, [Path::new("~/.config"), Path::new("/etc/app")]);
+        assert_eq!(config.strategy, Strategy::Copy);
+        assert!(config.enabled);
+    }
+
+    #[test]
+    fn parse_legacy_empty() {
+        assert!(NamespaceConfig::parse_legacy("").targets.is_empty());
+    }
+}
@@ -1,17 +1,53 @@
+use globset::{GlobBuilder, GlobMatcher};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "wasm")]
+use std::sync::OnceLock;
 use std::{
     collections::{BTreeMap, HashMap},
-    io::{Read, Write},
-    path::PathBuf,
+    io::Write,
+    path::{Component, Path, PathBuf},
     process::Stdio,
+    sync::Mutex,
 };
 use thiserror::Error;
 use tracing::{debug, info};
 
+use crate::{deployment::Transform, facts::Facts, secret};
+
+pub mod rpc;
+pub mod template;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Plugin {} failed with code {}", .plugin_name, .code)]
     PluginError { plugin_name: String, code: i32 },
+    #[error("Failed to render template '{}': {}", .name, .source)]
+    TemplateError {
+        name: String,
+        source: minijinja::Error,
+    },
+    #[error("Template '{}' is not valid UTF-8", .0)]
+    InvalidTemplate(String),
+    #[error("Plugin {} failed: {}", .plugin_name, .message)]
+    PluginFailed {
+        plugin_name: String,
+        message: String,
+    },
+    #[error("Plugin {} broke the protocol: {}", .plugin_name, .message)]
+    ProtocolError {
+        plugin_name: String,
+        message: String,
+    },
+    #[error("Plugin {} was called on a file, but not given one", .0)]
+    MissingFile(String),
+    #[error("Plugin {} does not implement the '{}' hook", .plugin_name, .hook)]
+    UnsupportedHook { plugin_name: String, hook: String },
+    #[error("Plugin {} is a .wasm plugin, but fig was built without the `wasm` feature", .0)]
+    WasmUnsupported(String),
+    #[error(transparent)]
+    SecretError(#[from] crate::secret::Error),
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
@@ -23,41 +59,181 @@ pub enum LoadPluginConfigError {
     ParseError(#[from] toml::de::Error),
     #[error(transparent)]
     FromMapError(#[from] FromMapError),
+    #[error("Unknown trigger '{}' in plugin {}. Expected \"repo\", an extension like \".txt\", a path like \"config/nvim/init.lua\" or a glob like \"**/*.lua\", optionally after a namespace like \"home:.txt\"", .trigger, .plugin)]
+    UnknownTrigger { plugin: String, trigger: String },
+    #[error("Plugin {} is a .wasm plugin, which can only use the stdio protocol", .0)]
+    WasmProtocol(String),
+    #[error("Directory '{}' in the allow_dirs of plugin {} is not in the repository. Expected a relative path without '..'", .dir, .plugin)]
+    InvalidAllowDir { plugin: String, dir: String },
+    #[error("Plugin {} is a .wasm file outside the repository ('{}'). Expected a relative path without '..'", .plugin, .cmd)]
+    InvalidWasmPath { plugin: String, cmd: String },
+    #[error("Unknown trigger '{}' in pipeline {}. Expected an extension like \".txt\", a path like \"config/nvim/init.lua\" or a glob like \"**/*.lua\", optionally after a namespace like \"home:.txt\"", .trigger, .pipeline)]
+    UnknownPipelineTrigger { pipeline: usize, trigger: String },
 }
 
 #[derive(Debug, Default)]
 pub struct PluginTriggerLookup<'a> {
     pub repository: Vec<&'a PluginInfo>,
-    pub file: HashMap<String, &'a PluginInfo>,
+    /// Plugins by the extension they trigger on and the namespace they are limited to,
+    /// in the order they run.
+    pub file: HashMap<(Option<String>, String), Vec<&'a PluginInfo>>,
+    /// Plugins that trigger on paths or globs, in the order they run.
+    pub paths: Vec<PathTrigger<'a>>,
+    /// Pipelines, with the highest priority first.
+    pub pipelines: Vec<Pipeline<'a>>,
+    /// Every plugin, so the running ones can be shut down.
+    plugins: Vec<&'a PluginInfo>,
+}
+
+impl Drop for PluginTriggerLookup<'_> {
+    fn drop(&mut self) {
+        for plugin in &self.plugins {
+            rpc::shutdown(plugin);
+        }
+    }
+}
+
+/// Files in a namespace whose path matches a glob.
+/// The path is relative to the namespace, or to the repository, e.g. `init.lua` or `config/init.lua`.
+#[derive(Debug)]
+pub struct FileMatcher {
+    pub namespace: Option<String>,
+    pub glob: GlobMatcher,
+}
+
+/// A plugin that runs on files whose path matches a glob, see [`FileMatcher`].
+#[derive(Debug)]
+pub struct PathTrigger<'a> {
+    pub matcher: FileMatcher,
+    pub plugin: &'a PluginInfo,
+}
+
+/// Steps that matching files are run through, in order, instead of the plugins their triggers
+/// would run.
+#[derive(Debug)]
+pub struct Pipeline<'a> {
+    pub priority: i32,
+    pub triggers: Vec<FileMatcher>,
+    pub steps: Vec<Transform<'a>>,
+}
+
+/// The contents of plugins.toml, where every table except `pipelines` is a plugin.
+#[derive(Debug, Deserialize)]
+struct PluginsFile {
+    #[serde(default)]
+    pipelines: Vec<PipelineSerde>,
+    #[serde(flatten)]
+    plugins: BTreeMap<String, PluginSerde>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 struct PluginSerde {
     cmd: String,
     triggers: Vec<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    protocol: Protocol,
+    #[serde(default)]
+    allow_dirs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PipelineSerde {
+    triggers: Vec<String>,
+    /// Names of plugins, `template` or `age`.
+    pipeline: Vec<String>,
+    #[serde(default)]
+    priority: i32,
+}
+
+/// A pipeline from plugins.toml, before its steps are looked up.
+#[derive(Debug)]
+pub struct PipelineInfo {
+    pub priority: i32,
+    pub triggers: Vec<Trigger>,
+    pub steps: Vec<String>,
 }
-impl Into<PluginInfo> for PluginSerde {
-    fn into(self) -> PluginInfo {
+
+impl PipelineSerde {
+    /// `number` counts from 1, as shown to the user.
+    fn into_info(self, number: usize) -> Result<PipelineInfo, LoadPluginConfigError> {
         let triggers = self
             .triggers
             .into_iter()
-            .map(|trigger| match trigger.as_str() {
-                "repo" => Trigger::Repository,
-                ext if ext.starts_with(".") => {
-                    Trigger::File(ext.strip_prefix('.').unwrap().to_string())
-                }
-                _ => {
-                    todo!()
+            .map(|trigger| match Trigger::parse(&trigger) {
+                Some(Trigger::Repository) | None => {
+                    Err(LoadPluginConfigError::UnknownPipelineTrigger {
+                        pipeline: number,
+                        trigger,
+                    })
                 }
+                Some(trigger) => Ok(trigger),
             })
-            .collect();
-        PluginInfo {
-            cmd: self.cmd,
+            .collect::<Result<_, _>>()?;
+        Ok(PipelineInfo {
+            priority: self.priority,
             triggers,
+            steps: self.pipeline,
+        })
+    }
+}
+
+impl PluginSerde {
+    fn into_info(self, name: String) -> Result<PluginInfo, LoadPluginConfigError> {
+        let triggers = self
+            .triggers
+            .into_iter()
+            .map(|trigger| {
+                Trigger::parse(&trigger).ok_or_else(|| LoadPluginConfigError::UnknownTrigger {
+                    plugin: name.clone(),
+                    trigger,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        if self.cmd.ends_with(".wasm") && self.protocol != Protocol::Stdio {
+            return Err(LoadPluginConfigError::WasmProtocol(name));
         }
+        if self.cmd.ends_with(".wasm") && !is_repository_path(&self.cmd) {
+            return Err(LoadPluginConfigError::InvalidWasmPath {
+                plugin: name,
+                cmd: self.cmd,
+            });
+        }
+        if let Some(dir) = self.allow_dirs.iter().find(|dir| !is_repository_path(dir)) {
+            return Err(LoadPluginConfigError::InvalidAllowDir {
+                plugin: name,
+                dir: dir.clone(),
+            });
+        }
+        Ok(PluginInfo {
+            name,
+            cmd: self.cmd,
+            triggers,
+            priority: self.priority,
+            args: self.args,
+            env: self.env,
+            protocol: self.protocol,
+            allow_dirs: self.allow_dirs,
+            session: Mutex::default(),
+            #[cfg(feature = "wasm")]
+            module: OnceLock::new(),
+        })
     }
 }
 
+/// Whether `path` is in the repository, which means it is relative and has no `..`.
+fn is_repository_path(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
 pub fn load_plugins(path: PathBuf) -> Result<PluginTriggerLookup<'static>, LoadPluginConfigError> {
     use LoadPluginConfigError::*;
 
@@ -68,24 +244,157 @@ pub fn load_plugins(path: PathBuf) -> Result<PluginTriggerLookup<'static>, LoadP
     }
 
     let text = std::fs::read_to_string(&path).map_err(ReadError)?;
-    let map: BTreeMap<String, PluginSerde> = toml::from_str(&text).map_err(ParseError)?;
+    let file: PluginsFile = toml::from_str(&text).map_err(ParseError)?;
 
-    let map = map
+    let pipelines = file
+        .pipelines
+        .into_iter()
+        .enumerate()
+        .map(|(i, pipeline)| pipeline.into_info(i + 1))
+        .collect::<Result<Vec<_>, _>>()?;
+    let map = file
+        .plugins
         .into_iter()
-        .map(|(name, plugin_info)| (name, plugin_info.into()))
-        .collect::<HashMap<String, PluginInfo>>();
+        .map(|(name, plugin)| Ok((name.clone(), plugin.into_info(name)?)))
+        .collect::<Result<HashMap<String, PluginInfo>, LoadPluginConfigError>>()?;
     let map = Box::new(map);
     let map = Box::leak(map);
 
-    debug!(plugins = ?map, "Loaded plugins");
+    debug!(plugins = ?map, ?pipelines, "Loaded plugins");
+
+    Ok(PluginTriggerLookup::from_map(map, &pipelines)?)
+}
+
+/// Where a plugin is run, which it is told about in `FIG_*` environment variables.
+#[derive(Debug)]
+pub struct Invocation<'a> {
+    pub repository: &'a Path,
+    /// Variables from vars.toml, for the current machine.
+    pub vars: &'a toml::Table,
+    /// The file the plugin is run on, or `None` when it is run on the repository.
+    pub file: Option<FileInvocation<'a>>,
+}
+
+#[derive(Debug)]
+pub struct FileInvocation<'a> {
+    /// The file in the repository.
+    pub source: &'a Path,
+    /// Where it is deployed to.
+    pub dest: &'a Path,
+    pub namespace: &'a str,
+    /// The target of the namespace it is deployed to.
+    pub target: &'a Path,
+}
+
+impl PluginInfo {
+    /// The command to run the plugin with, its arguments, and its environment.
+    fn command(&self, invocation: &Invocation) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.cmd);
+        command.args(&self.args);
+        command.envs(self.environment(invocation));
+        command
+    }
+
+    /// The environment variables the plugin is run with.
+    fn environment(&self, invocation: &Invocation) -> Vec<(String, String)> {
+        let facts = Facts::get();
+        let mut env = vec![
+            ("FIG_REPO", invocation.repository.display().to_string()),
+            ("FIG_HOSTNAME", facts.hostname.clone()),
+            ("FIG_OS", facts.os.clone()),
+            ("FIG_FAMILY", facts.family.clone()),
+            ("FIG_ARCH", facts.arch.clone()),
+            ("FIG_USER", facts.username.clone()),
+        ];
+        if let Some(file) = &invocation.file {
+            env.extend([
+                ("FIG_SOURCE_PATH", file.source.display().to_string()),
+                ("FIG_DEST_PATH", file.dest.display().to_string()),
+                ("FIG_NAMESPACE", file.namespace.to_string()),
+                ("FIG_TARGET", file.target.display().to_string()),
+            ]);
+        }
+        let mut env = env
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect::<Vec<_>>();
+
+        // Tables and arrays are only in FIG_VARS, which plugins can parse as JSON.
+        if let Ok(json) = serde_json::to_string(invocation.vars) {
+            env.push(("FIG_VARS".to_string(), json));
+        }
+        for (name, value) in flatten_vars(invocation.vars) {
+            env.push((format!("FIG_VAR_{name}"), value));
+        }
+
+        // Set last, so plugins.toml can override anything.
+        env.extend(self.env.clone());
+        env
+    }
+
+    /// Whether the plugin is a `.wasm` file in the repository, rather than a command.
+    fn is_wasm(&self) -> bool {
+        self.cmd.ends_with(".wasm")
+    }
+}
+
+#[cfg(feature = "wasm")]
+use wasm::{call_on_file as call_wasm_on_file, call_on_repository as call_wasm_on_repository};
+
+#[cfg(not(feature = "wasm"))]
+fn call_wasm_on_file(plugin: &PluginInfo, _: &Invocation, _: Vec<u8>) -> Result<Vec<u8>, Error> {
+    Err(Error::WasmUnsupported(plugin.name.clone()))
+}
 
-    Ok(PluginTriggerLookup::from_map(map)?)
+#[cfg(not(feature = "wasm"))]
+fn call_wasm_on_repository(plugin: &PluginInfo, _: &Invocation) -> Result<(), Error> {
+    Err(Error::WasmUnsupported(plugin.name.clone()))
 }
 
-pub fn call_on_file(cmd: &String, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+/// Variables as environment variable names and values, e.g. `git.email` as `GIT_EMAIL`.
+fn flatten_vars(vars: &toml::Table) -> Vec<(String, String)> {
+    let mut flattened = vec![];
+    for (key, value) in vars {
+        let key = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        match value {
+            toml::Value::String(value) => flattened.push((key, value.clone())),
+            toml::Value::Table(table) => flattened.extend(
+                flatten_vars(table)
+                    .into_iter()
+                    .map(|(name, value)| (format!("{key}_{name}"), value)),
+            ),
+            toml::Value::Array(_) => {}
+            value => flattened.push((key, value.to_string())),
+        }
+    }
+    flattened
+}
+
+pub fn call_on_file(
+    plugin: &PluginInfo,
+    invocation: &Invocation,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    if plugin.is_wasm() {
+        return call_wasm_on_file(plugin, invocation, bytes);
+    }
+    if plugin.protocol == Protocol::JsonRpc {
+        return rpc::call_on_file(plugin, invocation, bytes);
+    }
+
+    let cmd = &plugin.cmd;
     debug!("Calling plugin '{}'", cmd);
 
-    let mut command = std::process::Command::new(cmd);
+    let mut command = plugin.command(invocation);
 
     command.stdin(Stdio::piped()).stdout(Stdio::piped());
 
@@ -93,21 +402,24 @@ pub fn call_on_file(cmd: &String, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
 
     let mut child = command.spawn()?;
     let mut stdin = child.stdin.take().unwrap();
-    let mut stdout = child.stdout.take().unwrap();
 
-    stdin.write_all(&bytes)?;
+    // Write on another thread, so a plugin that streams its output can't fill the pipe and deadlock.
+    let writer = std::thread::spawn(move || {
+        stdin.write_all(&bytes)
+        // Dropping stdin closes it, so the plugin sees EOF.
+    });
 
     // TODO: Handle errors in the plugin
-    let status = child.wait()?;
-    if !status.success() {
+    let output = child.wait_with_output()?;
+    writer.join().expect("Plugin stdin writer panicked")?;
+    if !output.status.success() {
         return Err(Error::PluginError {
-            plugin_name: cmd.clone(),
-            code: status.code().take().unwrap_or(-1),
+            plugin_name: plugin.name.clone(),
+            code: output.status.code().unwrap_or(-1),
         });
     }
 
-    let mut buf = Vec::new();
-    stdout.read_to_end(&mut buf)?;
+    let buf = output.stdout;
 
     let output = std::str::from_utf8(&buf).unwrap_or("INVALID_UTF8");
     let output = format!("\"\n{}\"", truncate_string(output, 5));
@@ -117,16 +429,23 @@ pub fn call_on_file(cmd: &String, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
     Ok(buf)
 }
 
-pub fn call_on_repository(cmd: &String, repo_path: &PathBuf) -> std::io::Result<()> {
-    debug!("Calling plugin {cmd} on repository");
+pub fn call_on_repository(plugin: &PluginInfo, invocation: &Invocation) -> Result<(), Error> {
+    if plugin.is_wasm() {
+        return call_wasm_on_repository(plugin, invocation);
+    }
+    if plugin.protocol == Protocol::JsonRpc {
+        return rpc::call_on_repository(plugin, invocation);
+    }
 
-    let mut command = std::process::Command::new(cmd);
+    debug!("Calling plugin {} on repository", plugin.cmd);
 
-    command.arg(repo_path);
+    let mut command = plugin.command(invocation);
+
+    command.arg(invocation.repository);
     command.env("FIG_TRIGGER", "REPOSITORY");
 
-    let output = command.output();
-    output.map(|_| ())
+    command.output()?;
+    Ok(())
 }
 
 fn truncate_string(string: impl AsRef<str>, line_count: usize) -> String {
@@ -143,52 +462,459 @@ fn truncate_string(string: impl AsRef<str>, line_count: usize) -> String {
 }
 
 impl<'a> PluginTriggerLookup<'a> {
-    pub fn from_map(map: &'a HashMap<String, PluginInfo>) -> Result<Self, FromMapError> {
+    pub fn from_map(
+        map: &'a HashMap<String, PluginInfo>,
+        pipelines: &[PipelineInfo],
+    ) -> Result<Self, FromMapError> {
         let mut me = Self::default();
 
-        for (_, plugin_info) in map {
+        // Plugins triggered by the same file run in this order.
+        let mut plugins = map.values().collect::<Vec<_>>();
+        plugins.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.name.cmp(&b.name)));
+        for plugin_info in plugins {
+            me.plugins.push(plugin_info);
             for trigger in &plugin_info.triggers {
+                let (namespace, trigger) = match trigger {
+                    Trigger::Namespace { namespace, trigger } => (Some(namespace), &**trigger),
+                    trigger => (None, trigger),
+                };
                 match trigger {
                     Trigger::Repository => {
-                        me.repository.push(&plugin_info);
+                        me.repository.push(plugin_info);
                     }
-                    Trigger::File(ext) => match me.file.insert(ext.clone(), &plugin_info) {
-                        Some(old) => {
-                            return Err(FromMapError::ConflictingPluginTriggers {
-                                ext: ext.clone(),
-                                plugin1: old.cmd.clone(),
-                                plugin2: plugin_info.cmd.clone(),
-                            });
-                        }
-                        None => {}
-                    },
+                    Trigger::File(ext) => {
+                        me.file
+                            .entry((namespace.cloned(), ext.clone()))
+                            .or_default()
+                            .push(plugin_info);
+                    }
+                    Trigger::Glob(glob) => {
+                        let matcher = FileMatcher::new(namespace, glob).map_err(|source| {
+                            FromMapError::InvalidGlob {
+                                owner: format!("plugin {}", plugin_info.name),
+                                glob: glob.clone(),
+                                source,
+                            }
+                        })?;
+                        me.paths.push(PathTrigger {
+                            matcher,
+                            plugin: plugin_info,
+                        });
+                    }
+                    Trigger::Namespace { .. } => unreachable!("Namespaces can not be nested"),
                 }
             }
         }
 
+        for (i, pipeline) in pipelines.iter().enumerate() {
+            let number = i + 1;
+            let mut triggers = vec![];
+            for trigger in &pipeline.triggers {
+                let (namespace, trigger) = match trigger {
+                    Trigger::Namespace { namespace, trigger } => (Some(namespace), &**trigger),
+                    trigger => (None, trigger),
+                };
+                // Unlike for plugins, an extension stays in the name of the deployed file.
+                let glob = match trigger {
+                    Trigger::File(ext) => format!("**/*.{}", globset::escape(ext)),
+                    Trigger::Glob(glob) => glob.clone(),
+                    Trigger::Repository | Trigger::Namespace { .. } => {
+                        unreachable!("Checked when parsing the pipeline")
+                    }
+                };
+                triggers.push(FileMatcher::new(namespace, &glob).map_err(|source| {
+                    FromMapError::InvalidGlob {
+                        owner: format!("pipeline {number}"),
+                        glob: glob.clone(),
+                        source,
+                    }
+                })?);
+            }
+
+            let steps = pipeline
+                .steps
+                .iter()
+                .map(|step| match step.as_str() {
+                    template::NAME => Ok(Transform::Template),
+                    secret::NAME => Ok(Transform::Decrypt),
+                    name => map.get(name).map(Transform::Plugin).ok_or_else(|| {
+                        FromMapError::UnknownStep {
+                            pipeline: number,
+                            step: step.clone(),
+                        }
+                    }),
+                })
+                .collect::<Result<_, _>>()?;
+
+            me.pipelines.push(Pipeline {
+                priority: pipeline.priority,
+                triggers,
+                steps,
+            });
+        }
+        // Pipelines with the same priority stay in the order of plugins.toml.
+        me.pipelines
+            .sort_by_key(|pipeline| std::cmp::Reverse(pipeline.priority));
+
         Ok(me)
     }
+
+    /// Plugins for files with the extension `ext` in `namespace`, in the order they run.
+    pub fn for_extension(&self, namespace: &str, ext: &str) -> Vec<&'a PluginInfo> {
+        let mut plugins = [Some(namespace.to_string()), None]
+            .into_iter()
+            .filter_map(|namespace| self.file.get(&(namespace, ext.to_string())))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        plugins.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.name.cmp(&b.name)));
+        plugins
+    }
+
+    /// Plugins for a file in `namespace`, by its path in the repository or where it is deployed,
+    /// both relative to the namespace.
+    pub fn for_path<'s>(
+        &'s self,
+        namespace: &'s str,
+        source: &'s Path,
+        dest: &'s Path,
+    ) -> impl Iterator<Item = &'a PluginInfo> + 's {
+        self.paths
+            .iter()
+            .filter(move |trigger| trigger.matcher.is_match(namespace, source, dest))
+            .map(|trigger| trigger.plugin)
+    }
+
+    /// The pipeline with the highest priority for a file, like [`Self::for_path`].
+    pub fn pipeline_for(
+        &self,
+        namespace: &str,
+        source: &Path,
+        dest: &Path,
+    ) -> Option<&Pipeline<'a>> {
+        self.pipelines.iter().find(|pipeline| {
+            pipeline
+                .triggers
+                .iter()
+                .any(|matcher| matcher.is_match(namespace, source, dest))
+        })
+    }
+}
+
+impl FileMatcher {
+    fn new(namespace: Option<&String>, glob: &str) -> Result<FileMatcher, globset::Error> {
+        let glob = GlobBuilder::new(glob).literal_separator(true).build()?;
+        Ok(FileMatcher {
+            namespace: namespace.cloned(),
+            glob: glob.compile_matcher(),
+        })
+    }
+
+    /// `source` and `dest` are relative to the namespace.
+    pub fn is_match(&self, namespace: &str, source: &Path, dest: &Path) -> bool {
+        let in_repository = |path: &Path| Path::new(namespace).join(path);
+        self.namespace.as_deref().is_none_or(|ns| ns == namespace)
+            && [source, dest]
+                .into_iter()
+                .any(|path| self.glob.is_match(path) || self.glob.is_match(in_repository(path)))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PluginInfo {
+    /// The name of the plugin in plugins.toml.
+    #[serde(default)]
+    pub name: String,
     pub cmd: String,
     triggers: Vec<Trigger>,
+    /// Plugins with a higher priority run first, when several trigger on the same file.
+    #[serde(default)]
+    pub priority: i32,
+    /// Arguments the command is run with.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment variables the command is run with.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// Directories in the repository a `.wasm` plugin can read.
+    #[serde(default)]
+    pub allow_dirs: Vec<String>,
+    /// The running plugin, for the `json-rpc` protocol.
+    #[serde(skip)]
+    session: Mutex<rpc::SessionState>,
+    /// The compiled plugin, for `.wasm` plugins.
+    #[cfg(feature = "wasm")]
+    #[serde(skip)]
+    module: OnceLock<wasm::CompiledModule>,
+}
+
+/// How fig talks to a plugin.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// The plugin is run for every file, with its contents on stdin and the result on stdout.
+    #[default]
+    Stdio,
+    /// The plugin is run once, and sent every file as a JSON-RPC request. See [`rpc`].
+    JsonRpc,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Trigger {
     Repository,
+    /// Files with this extension, which is trimmed from where they are deployed to.
     File(String),
+    /// Files whose path in the namespace or the repository matches this glob.
+    Glob(String),
+    /// A trigger that only applies to files in one namespace.
+    Namespace {
+        namespace: String,
+        trigger: Box<Trigger>,
+    },
+}
+
+impl Trigger {
+    /// Parse a trigger from plugins.toml, e.g. `repo`, `.txt`, `config/**/*.lua` or `home:.txt`.
+    fn parse(trigger: &str) -> Option<Trigger> {
+        if trigger == "repo" {
+            return Some(Trigger::Repository);
+        }
+        if let Some((namespace, trigger)) = trigger.split_once(':') {
+            if namespace.is_empty() || namespace.contains(['/', '\\', '*', '?', '[', '{']) {
+                return None;
+            }
+            return Some(Trigger::Namespace {
+                namespace: namespace.to_string(),
+                trigger: Box::new(Self::parse_file(trigger)?),
+            });
+        }
+        Self::parse_file(trigger)
+    }
+
+    /// `.name` is always an extension, so a file like `.bashrc` is matched with `./.bashrc`.
+    fn parse_file(trigger: &str) -> Option<Trigger> {
+        let is_glob = trigger.contains(['*', '?', '[', '{']);
+        if let Some(ext) = trigger.strip_prefix('.') {
+            if !is_glob && !ext.is_empty() && !ext.contains(['.', '/', '\\']) {
+                return Some(Trigger::File(ext.to_string()));
+            }
+        }
+
+        let path = trigger.strip_prefix("./").unwrap_or(trigger);
+        if path.is_empty() || path == "." {
+            None
+        } else if is_glob {
+            Some(Trigger::Glob(path.to_string()))
+        } else {
+            // A path is a glob that only matches itself.
+            Some(Trigger::Glob(globset::escape(path)))
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum FromMapError {
-    #[error("Conflicting plugins!: Both {} and {} trigger on '.{}'", .plugin1, .plugin2, .ext)]
-    ConflictingPluginTriggers {
-        ext: String,
-        plugin1: String,
-        plugin2: String,
+    #[error("Invalid glob '{}' in {}", .glob, .owner)]
+    InvalidGlob {
+        /// The plugin or pipeline, e.g. `plugin minify`.
+        owner: String,
+        glob: String,
+        source: globset::Error,
     },
+    #[error("Unknown step '{}' in pipeline {}, expected a plugin in plugins.toml, \"{}\" or \"{}\"", .step, .pipeline, template::NAME, secret::NAME)]
+    UnknownStep { pipeline: usize, step: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(plugins: &str) -> Result<PluginTriggerLookup<'static>, LoadPluginConfigError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plugins.toml");
+        std::fs::write(&path, plugins).unwrap();
+        load_plugins(path)
+    }
+
+    fn lookup(plugins: &str) -> PluginTriggerLookup<'static> {
+        load(plugins).unwrap()
+    }
+
+    fn names(plugins: &[&PluginInfo]) -> Vec<String> {
+        plugins.iter().map(|plugin| plugin.name.clone()).collect()
+    }
+
+    fn steps(pipeline: Option<&Pipeline>) -> Vec<String> {
+        pipeline
+            .expect("a pipeline matches")
+            .steps
+            .iter()
+            .map(|step| match step {
+                Transform::Template => template::NAME.to_string(),
+                Transform::Decrypt => secret::NAME.to_string(),
+                Transform::Plugin(plugin) => plugin.name.clone(),
+            })
+            .collect()
+    }
+
+    fn glob(trigger: &str) -> String {
+        match Trigger::parse(trigger) {
+            Some(Trigger::Glob(glob)) => glob,
+            other => panic!("'{trigger}' is not a glob: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_repository() {
+        assert!(matches!(Trigger::parse("repo"), Some(Trigger::Repository)));
+    }
+
+    #[test]
+    fn parse_extension() {
+        assert!(matches!(Trigger::parse(".txt"), Some(Trigger::File(ext)) if ext == "txt"));
+        // A name starting with a `.` is an extension too.
+        assert!(matches!(Trigger::parse(".bashrc"), Some(Trigger::File(ext)) if ext == "bashrc"));
+    }
+
+    #[test]
+    fn parse_path() {
+        assert_eq!(glob("init.lua"), "init.lua");
+        assert_eq!(glob("config/starship.toml"), "config/starship.toml");
+        assert_eq!(glob("./.bashrc"), ".bashrc");
+        assert_eq!(glob(".config/nvim"), ".config/nvim");
+    }
+
+    #[test]
+    fn parse_glob() {
+        assert_eq!(glob("config/nvim/**/*.lua"), "config/nvim/**/*.lua");
+        assert_eq!(glob("*.lua"), "*.lua");
+        assert_eq!(glob(".*rc"), ".*rc");
+    }
+
+    #[test]
+    fn parse_namespace() {
+        let Some(Trigger::Namespace { namespace, trigger }) = Trigger::parse("home:.txt") else {
+            panic!("not a namespace trigger");
+        };
+        assert_eq!(namespace, "home");
+        assert!(matches!(*trigger, Trigger::File(ext) if ext == "txt"));
+
+        let Some(Trigger::Namespace { trigger, .. }) = Trigger::parse("config:nvim/**/*.lua")
+        else {
+            panic!("not a namespace trigger");
+        };
+        assert!(matches!(*trigger, Trigger::Glob(glob) if glob == "nvim/**/*.lua"));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for trigger in ["", ".", "./", ":.txt", "home:", "a/b:.txt", "*:.txt"] {
+            assert!(Trigger::parse(trigger).is_none(), "{trigger}");
+        }
+    }
+
+    #[test]
+    fn match_relative_to_namespace_or_repository() {
+        let matcher = FileMatcher::new(None, "config/nvim/**/*.lua").unwrap();
+        let source = Path::new("nvim/lua/init.lua.tmpl");
+        let dest = Path::new("nvim/lua/init.lua");
+        assert!(matcher.is_match("config", source, dest));
+        assert!(!matcher.is_match("home", source, dest));
+
+        let matcher = FileMatcher::new(None, "nvim/**/*.lua").unwrap();
+        assert!(matcher.is_match("config", source, dest));
+
+        let matcher = FileMatcher::new(Some(&"home".to_string()), "**/*.lua").unwrap();
+        assert!(matcher.is_match("home", source, dest));
+        assert!(!matcher.is_match("config", source, dest));
+    }
+
+    const PLUGINS: &str = r#"
+[[pipelines]]
+triggers = ["config/nvim/**/*.lua"]
+pipeline = ["template", "b"]
+
+[[pipelines]]
+triggers = ["**/*.lua"]
+pipeline = ["a"]
+
+[[pipelines]]
+triggers = ["home:.js"]
+pipeline = ["age", "template", "b"]
+priority = 10
+
+[a]
+cmd = "a"
+triggers = [".txt"]
+
+[b]
+cmd = "b"
+triggers = [".txt"]
+priority = 10
+
+[c]
+cmd = "c"
+triggers = ["home:.txt"]
+"#;
+
+    #[test]
+    fn extension_order() {
+        let lookup = lookup(PLUGINS);
+        assert_eq!(names(&lookup.for_extension("home", "txt")), ["b", "a", "c"]);
+        assert_eq!(names(&lookup.for_extension("config", "txt")), ["b", "a"]);
+        assert!(lookup.for_extension("home", "md").is_empty());
+    }
+
+    #[test]
+    fn pipeline_priority() {
+        let lookup = lookup(PLUGINS);
+        let pipeline =
+            |namespace, path| lookup.pipeline_for(namespace, Path::new(path), Path::new(path));
+
+        // With the same priority, the first pipeline in plugins.toml is used.
+        assert_eq!(
+            steps(pipeline("config", "nvim/init.lua")),
+            ["template", "b"]
+        );
+        assert_eq!(steps(pipeline("home", "init.lua")), ["a"]);
+        // Steps run in the order they are listed.
+        assert_eq!(steps(pipeline("home", "app.js")), ["age", "template", "b"]);
+        assert!(pipeline("config", "app.js").is_none());
+    }
+
+    #[test]
+    fn pipeline_higher_priority_first() {
+        let lookup = lookup(&format!(
+            "{PLUGINS}\n[[pipelines]]\ntriggers = [\"init.lua\"]\npipeline = [\"c\"]\npriority = 5\n"
+        ));
+        let path = Path::new("nvim/init.lua");
+        assert_eq!(
+            steps(lookup.pipeline_for("config", path, path)),
+            ["template", "b"]
+        );
+        let path = Path::new("init.lua");
+        assert_eq!(steps(lookup.pipeline_for("config", path, path)), ["c"]);
+    }
+
+    #[test]
+    fn wasm_outside_repository() {
+        for cmd in [
+            "../minify.wasm",
+            "/plugins/minify.wasm",
+            "plugins/../../minify.wasm",
+        ] {
+            let plugins = format!("[minify]\ncmd = \"{cmd}\"\ntriggers = [\".js\"]\n");
+            assert!(
+                matches!(
+                    load(&plugins),
+                    Err(LoadPluginConfigError::InvalidWasmPath { .. })
+                ),
+                "{cmd}"
+            );
+        }
+        assert!(load("[minify]\ncmd = \"plugins/minify.wasm\"\ntriggers = [\".js\"]\n").is_ok());
+    }
 }
@@ -0,0 +1,471 @@
+//! Plugins that are started once, and exchange newline delimited JSON-RPC messages with fig.
+//!
+//! Fig first sends an `initialize` request, and the plugin answers with the hooks it implements.
+//! Then every file is sent in a `file` request, and the repository in a `repository` request.
+//! File contents are base64 encoded. When fig is done, it sends a `shutdown` notification,
+//! and closes the plugin's stdin.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    process::{Child, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::{Error, Invocation, PluginInfo};
+use crate::facts::Facts;
+
+/// The version of the protocol, sent to the plugin in `initialize`.
+pub const VERSION: u32 = 1;
+/// Hooks fig can call, a plugin says which of them it implements.
+pub const FILE_HOOK: &str = "file";
+pub const REPOSITORY_HOOK: &str = "repository";
+
+/// How long a plugin has to exit after it is told to shut down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a plugin has to respond to a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The session of a plugin, which is started when it is first called.
+#[derive(Debug, Default)]
+pub enum SessionState {
+    #[default]
+    NotStarted,
+    Running(Session),
+    /// The plugin could not be started, with the reason why.
+    /// It is not started again, so a plugin that hangs only holds up the first file.
+    Failed(String),
+}
+
+/// A running plugin.
+#[derive(Debug)]
+pub struct Session {
+    child: Child,
+    /// Messages for the plugin's stdin, which are written on another thread, so a plugin that
+    /// stops reading can't block fig.
+    stdin: Sender<Vec<u8>>,
+    /// Lines from the plugin's stdout, which are read on another thread so requests can time out.
+    lines: Receiver<std::io::Result<String>>,
+    next_id: u64,
+    /// The hooks the plugin implements.
+    hooks: Vec<String>,
+    /// Whether the plugin was killed for not responding.
+    killed: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Request<'a, P> {
+    jsonrpc: &'static str,
+    /// Notifications have no id, and get no response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    method: &'a str,
+    params: P,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    id: Option<u64>,
+    result: Option<serde_json::Value>,
+    error: Option<ResponseError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseError {
+    message: String,
+    #[serde(default)]
+    data: Option<ErrorData>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ErrorData {
+    #[serde(default)]
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Serialize)]
+struct InitializeParams<'a> {
+    version: u32,
+    repository: &'a Path,
+    /// Every hook fig knows about.
+    hooks: [&'static str; 2],
+}
+
+#[derive(Debug, Deserialize)]
+struct InitializeResult {
+    hooks: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FileParams<'a> {
+    /// The contents of the file, base64 encoded.
+    contents: String,
+    source: &'a Path,
+    dest: &'a Path,
+    namespace: &'a str,
+    target: &'a Path,
+    repository: &'a Path,
+    vars: &'a toml::Table,
+    facts: &'a Facts,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileResult {
+    /// The contents to deploy, base64 encoded.
+    contents: String,
+    #[serde(default)]
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Serialize)]
+struct RepositoryParams<'a> {
+    repository: &'a Path,
+    vars: &'a toml::Table,
+    facts: &'a Facts,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RepositoryResult {
+    #[serde(default)]
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Something a plugin has to say about a file, such as a lint warning.
+#[derive(Debug, Deserialize)]
+pub struct Diagnostic {
+    #[serde(default)]
+    pub level: Level,
+    pub message: String,
+    /// The line of the file it is about, counting from 1.
+    #[serde(default)]
+    pub line: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Error,
+    Warning,
+    #[default]
+    Info,
+}
+
+impl Level {
+    fn label(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+        }
+    }
+}
+
+impl Session {
+    fn start(plugin: &PluginInfo, invocation: &Invocation) -> Result<Session, Error> {
+        debug!("Starting plugin {}", plugin.name);
+
+        let mut command = plugin.command(invocation);
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .env("FIG_PROTOCOL", "json-rpc");
+        let mut child = command.spawn()?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let (stdin_sender, messages) = mpsc::channel::<Vec<u8>>();
+        std::thread::spawn(move || {
+            // Stops when the plugin stops reading, or the session is done and drops the sender.
+            for message in messages {
+                if stdin
+                    .write_all(&message)
+                    .and_then(|_| stdin.flush())
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            let mut line = String::new();
+            let result = match stdout.read_line(&mut line) {
+                // The plugin exited, which drops the sender.
+                Ok(0) => return,
+                Ok(_) => Ok(line),
+                Err(e) => Err(e),
+            };
+            if sender.send(result).is_err() {
+                return;
+            }
+        });
+
+        let mut session = Session {
+            stdin: stdin_sender,
+            lines,
+            child,
+            next_id: 1,
+            hooks: vec![],
+            killed: false,
+        };
+        let result: Result<InitializeResult, _> = session.request(
+            plugin,
+            "initialize",
+            InitializeParams {
+                version: VERSION,
+                repository: invocation.repository,
+                hooks: [FILE_HOOK, REPOSITORY_HOOK],
+            },
+            "",
+        );
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                session.shutdown();
+                return Err(e);
+            }
+        };
+        debug!(hooks = ?result.hooks, "Plugin {} started", plugin.name);
+        session.hooks = result.hooks;
+
+        Ok(session)
+    }
+
+    /// Send a request, and wait for its response.
+    /// `subject` is what the request is about, for printing diagnostics.
+    fn request<P: Serialize, R: DeserializeOwned>(
+        &mut self,
+        plugin: &PluginInfo,
+        method: &str,
+        params: P,
+        subject: &str,
+    ) -> Result<R, Error> {
+        let protocol_error = |message: String| Error::ProtocolError {
+            plugin_name: plugin.name.clone(),
+            message,
+        };
+        if self.killed {
+            return Err(protocol_error(
+                "it was stopped after it did not respond".to_string(),
+            ));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&Request {
+            jsonrpc: "2.0",
+            id: Some(id),
+            method,
+            params,
+        })
+        .map_err(|_| protocol_error(format!("it stopped reading before '{method}'")))?;
+
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let line = match self.lines.recv_timeout(timeout) {
+                Ok(line) => line?,
+                Err(RecvTimeoutError::Timeout) => {
+                    // Stop it, so every later file doesn't wait for it too.
+                    let _ = self.child.kill();
+                    let _ = self.child.wait();
+                    self.killed = true;
+                    return Err(protocol_error(format!(
+                        "it did not respond to '{method}' within {}s",
+                        REQUEST_TIMEOUT.as_secs()
+                    )));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(protocol_error(format!(
+                        "it exited before responding to '{method}'"
+                    )));
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response: Response = serde_json::from_str(&line)
+                .map_err(|e| protocol_error(format!("invalid response: {e}")))?;
+            // Plugins can not call fig, so anything else is a stray message.
+            if response.id != Some(id) {
+                warn!(?response, "Ignoring message from plugin {}", plugin.name);
+                continue;
+            }
+
+            if let Some(error) = response.error {
+                let data = error.data.unwrap_or_default();
+                print_diagnostics(plugin, subject, &data.diagnostics);
+                return Err(Error::PluginFailed {
+                    plugin_name: plugin.name.clone(),
+                    message: error.message,
+                });
+            }
+            let result = response.result.unwrap_or_default();
+            return serde_json::from_value(result)
+                .map_err(|e| protocol_error(format!("invalid result for '{method}': {e}")));
+        }
+    }
+
+    /// Queue a message for the plugin. Fails if the plugin stopped reading its stdin.
+    fn send<T: Serialize>(&mut self, message: &T) -> Result<(), mpsc::SendError<Vec<u8>>> {
+        let mut json = serde_json::to_vec(message).expect("Messages are always valid JSON");
+        json.push(b'\n');
+        self.stdin.send(json)
+    }
+
+    fn implements(&self, plugin: &PluginInfo, hook: &str) -> Result<(), Error> {
+        if self.hooks.iter().any(|h| h == hook) {
+            return Ok(());
+        }
+        Err(Error::UnsupportedHook {
+            plugin_name: plugin.name.clone(),
+            hook: hook.to_string(),
+        })
+    }
+
+    /// Tell the plugin to exit, and wait for it to do so.
+    fn shutdown(mut self) {
+        let _ = self.send(&Request {
+            jsonrpc: "2.0",
+            id: None,
+            method: "shutdown",
+            params: (),
+        });
+        // The writer thread closes stdin once the shutdown notification is written.
+        drop(self.stdin);
+
+        let start = Instant::now();
+        loop {
+            match self.child.try_wait() {
+                Ok(Some(_)) => return,
+                Ok(None) if start.elapsed() < SHUTDOWN_TIMEOUT => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                _ => {
+                    warn!("Plugin did not exit after shutting down, killing it");
+                    let _ = self.child.kill();
+                    let _ = self.child.wait();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Run `f` with the session of `plugin`, starting the plugin if it is not running yet.
+fn with_session<T>(
+    plugin: &PluginInfo,
+    invocation: &Invocation,
+    f: impl FnOnce(&mut Session) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut state = plugin.session.lock().unwrap_or_else(|e| e.into_inner());
+    if let SessionState::NotStarted = *state {
+        // The session is shared by every file, so it only knows about the repository.
+        let invocation = Invocation {
+            repository: invocation.repository,
+            vars: invocation.vars,
+            file: None,
+        };
+        match Session::start(plugin, &invocation) {
+            Ok(session) => *state = SessionState::Running(session),
+            Err(e) => {
+                *state = SessionState::Failed(e.to_string());
+                return Err(e);
+            }
+        }
+    }
+    match &mut *state {
+        SessionState::Running(session) => f(session),
+        SessionState::Failed(reason) => Err(Error::PluginFailed {
+            plugin_name: plugin.name.clone(),
+            message: format!("it did not start ({reason})"),
+        }),
+        SessionState::NotStarted => unreachable!("The plugin was just started"),
+    }
+}
+
+pub fn call_on_file(
+    plugin: &PluginInfo,
+    invocation: &Invocation,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let Some(file) = &invocation.file else {
+        return Err(Error::MissingFile(plugin.name.clone()));
+    };
+    with_session(plugin, invocation, |session| {
+        session.implements(plugin, FILE_HOOK)?;
+        let subject = file.dest.display().to_string();
+        let result: FileResult = session.request(
+            plugin,
+            FILE_HOOK,
+            FileParams {
+                contents: STANDARD.encode(bytes),
+                source: file.source,
+                dest: file.dest,
+                namespace: file.namespace,
+                target: file.target,
+                repository: invocation.repository,
+                vars: invocation.vars,
+                facts: Facts::get(),
+            },
+            &subject,
+        )?;
+        print_diagnostics(plugin, &subject, &result.diagnostics);
+        STANDARD
+            .decode(result.contents)
+            .map_err(|e| Error::ProtocolError {
+                plugin_name: plugin.name.clone(),
+                message: format!("contents are not valid base64: {e}"),
+            })
+    })
+}
+
+pub fn call_on_repository(plugin: &PluginInfo, invocation: &Invocation) -> Result<(), Error> {
+    with_session(plugin, invocation, |session| {
+        session.implements(plugin, REPOSITORY_HOOK)?;
+        let subject = invocation.repository.display().to_string();
+        let result: RepositoryResult = session.request(
+            plugin,
+            REPOSITORY_HOOK,
+            RepositoryParams {
+                repository: invocation.repository,
+                vars: invocation.vars,
+                facts: Facts::get(),
+            },
+            &subject,
+        )?;
+        print_diagnostics(plugin, &subject, &result.diagnostics);
+        Ok(())
+    })
+}
+
+/// Stop the plugin, if it was started.
+pub fn shutdown(plugin: &PluginInfo) {
+    let state = std::mem::take(&mut *plugin.session.lock().unwrap_or_else(|e| e.into_inner()));
+    if let SessionState::Running(session) = state {
+        debug!("Shutting down plugin {}", plugin.name);
+        session.shutdown();
+    }
+}
+
+fn print_diagnostics(plugin: &PluginInfo, subject: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        let location = match diagnostic.line {
+            Some(line) => format!("{subject}:{line}"),
+            None => subject.to_string(),
+        };
+        eprintln!(
+            "{}: {location}: {} ({})",
+            diagnostic.level.label(),
+            diagnostic.message,
+            plugin.name
+        );
+    }
+}
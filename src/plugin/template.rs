@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+
+use super::Error;
+
+/// Files with this extension are rendered as templates, without needing a plugin.
+pub const EXTENSION: &str = "tmpl";
+/// The name the template engine goes by, e.g. in the `disabled` plugins of a namespace.
+pub const NAME: &str = "template";
+
+/// What a template can use.
+#[derive(Debug, Serialize)]
+pub struct Context<'a> {
+    pub hostname: &'a str,
+    pub os: &'a str,
+    pub family: &'a str,
+    pub arch: &'a str,
+    pub user: &'a str,
+    pub env: BTreeMap<String, String>,
+    /// Name of the namespace the file is in.
+    pub namespace: &'a str,
+    /// The target the file is deployed to.
+    pub target: String,
+    /// Variables from vars.toml.
+    pub vars: &'a toml::Table,
+}
+
+/// Render `contents` as a template, `name` is only used in errors.
+pub fn render(name: &str, contents: Vec<u8>, context: &Context) -> Result<Vec<u8>, Error> {
+    let text = String::from_utf8(contents).map_err(|_| Error::InvalidTemplate(name.to_string()))?;
+
+    let mut env = Environment::new();
+    // Catch typos in variable names, instead of silently deploying an empty string.
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_keep_trailing_newline(true);
+
+    let template_error = |source| Error::TemplateError {
+        name: name.to_string(),
+        source,
+    };
+    env.add_template(name, &text).map_err(template_error)?;
+    let output = env
+        .get_template(name)
+        .and_then(|template| template.render(context))
+        .map_err(template_error)?;
+
+    Ok(output.into_bytes())
+}
@@ -0,0 +1,155 @@
+//! Plugins compiled to WebAssembly, which are stored in the repository and run in-process.
+//!
+//! A plugin is a WASI (preview 1) command. Like other plugins, it reads the file from stdin and
+//! writes the result to stdout, but it can not access the network, and only the directories
+//! listed in its `allow_dirs`.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use tracing::debug;
+use wasmtime::{Config, Engine, Linker, Module, Store, Trap};
+use wasmtime_wasi::{
+    pipe::{MemoryInputPipe, MemoryOutputPipe},
+    preview1::{self, WasiP1Ctx},
+    DirPerms, FilePerms, I32Exit, WasiCtxBuilder,
+};
+
+use super::{is_repository_path, Error, Invocation, PluginInfo};
+
+/// The most a plugin can write to stdout.
+const MAX_OUTPUT: usize = 64 * 1024 * 1024;
+/// How often the engine checks whether a plugin has run for too long.
+const EPOCH_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a plugin can run on a file, in epochs.
+const TIMEOUT_EPOCHS: u64 = 300;
+
+/// A compiled plugin.
+#[derive(Debug)]
+pub struct CompiledModule(Module);
+
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).expect("The engine config is valid");
+
+        // Plugins are interrupted once their deadline is this many epochs away.
+        let ticker = engine.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(EPOCH_INTERVAL);
+            ticker.increment_epoch();
+        });
+        engine
+    })
+}
+
+pub fn call_on_file(
+    plugin: &PluginInfo,
+    invocation: &Invocation,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    run(plugin, invocation, "FILE", bytes)
+}
+
+pub fn call_on_repository(plugin: &PluginInfo, invocation: &Invocation) -> Result<(), Error> {
+    run(plugin, invocation, "REPOSITORY", vec![]).map(|_| ())
+}
+
+fn run(
+    plugin: &PluginInfo,
+    invocation: &Invocation,
+    trigger: &str,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let wasm_error = |message: String| Error::PluginFailed {
+        plugin_name: plugin.name.clone(),
+        message,
+    };
+
+    // Compiling is slow, so it is only done the first time the plugin is called.
+    let module = match plugin.module.get() {
+        Some(module) => &module.0,
+        None => {
+            let path = invocation.repository.join(&plugin.cmd);
+            debug!("Compiling plugin {} from {}", plugin.name, path.display());
+            let module = repository_path(invocation.repository, &plugin.cmd)
+                .and_then(|path| Module::from_file(engine(), path))
+                .map_err(|e| wasm_error(format!("failed to load {}: {e:#}", path.display())))?;
+            &plugin.module.get_or_init(|| CompiledModule(module)).0
+        }
+    };
+
+    debug!("Calling plugin {}", plugin.name);
+
+    let stdout = MemoryOutputPipe::new(MAX_OUTPUT);
+    let mut builder = WasiCtxBuilder::new();
+    builder
+        .stdin(MemoryInputPipe::new(bytes))
+        .stdout(stdout.clone())
+        .inherit_stderr()
+        .arg(&plugin.name)
+        .args(&plugin.args)
+        .envs(&plugin.environment(invocation))
+        .env("FIG_TRIGGER", trigger);
+    for dir in &plugin.allow_dirs {
+        preopen(&mut builder, invocation.repository, dir)
+            .map_err(|e| wasm_error(format!("failed to open directory '{dir}': {e:#}")))?;
+    }
+
+    let mut linker = Linker::new(engine());
+    preview1::add_to_linker_sync(&mut linker, |ctx: &mut WasiP1Ctx| ctx)
+        .map_err(|e| wasm_error(format!("{e:#}")))?;
+    let mut store = Store::new(engine(), builder.build_p1());
+    store.set_epoch_deadline(TIMEOUT_EPOCHS);
+    let instance = linker
+        .instantiate(&mut store, module)
+        .map_err(|e| wasm_error(format!("{e:#}")))?;
+    let start = instance
+        .get_typed_func::<(), ()>(&mut store, "_start")
+        .map_err(|e| wasm_error(format!("it is not a WASI command: {e:#}")))?;
+
+    match start.call(&mut store, ()) {
+        Ok(()) => {}
+        Err(e) => match e.downcast_ref::<I32Exit>() {
+            Some(I32Exit(0)) => {}
+            Some(I32Exit(code)) => {
+                return Err(Error::PluginError {
+                    plugin_name: plugin.name.clone(),
+                    code: *code,
+                })
+            }
+            None if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => {
+                return Err(wasm_error(format!(
+                    "it did not finish within {}s",
+                    (EPOCH_INTERVAL * TIMEOUT_EPOCHS as u32).as_secs()
+                )))
+            }
+            None => return Err(wasm_error(format!("{e:#}"))),
+        },
+    }
+    drop(store);
+
+    Ok(stdout.contents().to_vec())
+}
+
+/// Give the plugin read-only access to `dir`, relative to the repository, at the same path.
+fn preopen(builder: &mut WasiCtxBuilder, repository: &Path, dir: &str) -> wasmtime::Result<()> {
+    let path = repository_path(repository, dir)?;
+    builder.preopened_dir(path, dir, DirPerms::READ, FilePerms::READ)?;
+    Ok(())
+}
+
+/// Resolve `relative` in the repository, making sure it does not lead out of it.
+fn repository_path(repository: &Path, relative: &str) -> wasmtime::Result<PathBuf> {
+    // The path could also be a link out of the repository.
+    let path = repository.join(relative).canonicalize()?;
+    if !is_repository_path(relative) || !path.starts_with(repository.canonicalize()?) {
+        return Err(wasmtime::Error::msg("it is not in the repository"));
+    }
+    Ok(path)
+}
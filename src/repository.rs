@@ -1,15 +1,23 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use color_eyre::{
     eyre::{bail, Context},
     Result,
 };
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
-    namespace::Namespace,
+    facts::Facts,
+    namespace::{Namespace, NamespaceConfig},
     plugin::{self, PluginTriggerLookup},
+    secret,
+    settings::Settings,
     template,
+    vars::VarsFile,
 };
 
 pub enum RepositoryBuilder {
@@ -71,7 +79,8 @@ impl RepositoryBuilder {
 
                 template::generate(&path)?;
 
-                let dot_gitignore = "namespace.fig";
+                // Private keys are never committed, even if one is copied into the repository.
+                let dot_gitignore = format!("namespace.fig\n{}\n", secret::IDENTITY_FILE);
                 let dot_gitignore_path = path.join(".gitignore");
                 std::fs::write(&dot_gitignore_path, dot_gitignore).wrap_err(format!(
                     "Failed to write to {}",
@@ -124,6 +133,77 @@ pub struct Repository {
     path: PathBuf,
 }
 
+/// What pulling changed.
+#[derive(Debug)]
+pub enum Pull {
+    UpToDate,
+    FastForward(git2::Oid),
+    /// Local commits were put on top of the upstream.
+    Rebased {
+        commits: usize,
+        onto: git2::Oid,
+    },
+}
+
+/// A commit that changed a file, as shown by `fig log`.
+#[derive(Debug)]
+pub struct LogEntry {
+    pub id: git2::Oid,
+    pub time: SystemTime,
+    pub author: String,
+    pub summary: String,
+}
+
+/// The state of the git repository, as shown by `fig info`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GitStatus {
+    /// The current branch, or `None` if HEAD is detached.
+    pub branch: Option<String>,
+    /// The branch it is tracking, e.g. `origin/main`, if it was fetched.
+    pub upstream: Option<String>,
+    /// Commits that are not on the upstream yet.
+    pub ahead: usize,
+    /// Commits on the upstream that are not pulled yet.
+    pub behind: usize,
+    /// An operation that was started but not finished, e.g. `merge` or `rebase`.
+    pub in_progress: Option<String>,
+    /// Files that differ from the last commit, by namespace.
+    pub namespaces: Vec<NamespaceStatus>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct NamespaceStatus {
+    /// `None` for files in the root of the repository, such as plugins.toml.
+    pub namespace: Option<String>,
+    /// Changes that will be committed.
+    pub staged: Vec<PathBuf>,
+    /// Changes that are not staged.
+    pub modified: Vec<PathBuf>,
+    pub untracked: Vec<PathBuf>,
+    /// Files with merge conflicts.
+    pub conflicted: Vec<PathBuf>,
+}
+
+struct Upstream {
+    remote: String,
+    /// The local branch.
+    branch: String,
+    /// The branch on the remote, e.g. `refs/heads/main`.
+    merge: String,
+}
+
+impl Upstream {
+    fn remote_branch(&self) -> &str {
+        self.merge
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&self.merge)
+    }
+
+    fn tracking_ref(&self) -> String {
+        format!("refs/remotes/{}/{}", self.remote, self.remote_branch())
+    }
+}
+
 impl Repository {
     pub fn into_builder(self) -> RepositoryBuilder {
         RepositoryBuilder::Opened(self)
@@ -138,31 +218,20 @@ impl Repository {
         let mut out = vec![];
         for entry in self.path.read_dir().wrap_err("Failed to read directory")? {
             let entry = entry?;
-            if entry.file_type()?.is_dir() && entry.path().join("namespace.fig").exists() {
-                let text = std::fs::read_to_string(entry.path().join("namespace.fig"))?;
-                let targets = text
-                    .lines()
-                    .into_iter()
-                    .map(str::trim)
-                    .filter(|l| !l.is_empty())
-                    .map(PathBuf::from)
-                    .filter_map(|path| path.canonicalize().ok())
-                    .collect();
-                let namespace = Namespace {
-                    targets,
-                    location: entry.path(),
-                };
-                out.push(namespace);
+            if entry.file_type()?.is_dir() {
+                if let Some(namespace) = Namespace::load(entry.path())? {
+                    out.push(namespace);
+                }
             }
         }
         Ok(out)
     }
 
-    /// List of all directories in repository that do not have a namespace.fig file.
+    /// List of all directories in repository that are not defined as a namespace.
     pub fn floating_namespaces(&self) -> Result<Vec<String>> {
         let mut floating_namespaces = Vec::new();
         for entry in self.path().read_dir()?.flatten() {
-            if entry.file_type()?.is_dir() && !entry.path().join("namespace.fig").exists() {
+            if entry.file_type()?.is_dir() && NamespaceConfig::read(&entry.path())?.is_none() {
                 let file_name = entry.file_name().to_str().unwrap().to_string();
                 // Ignore hidden directories (e.g. ".git")
                 if file_name.starts_with(".") {
@@ -174,15 +243,546 @@ impl Repository {
         Ok(floating_namespaces)
     }
 
+    /// Stage every change, and commit it. Returns `None` if nothing changed.
+    pub fn commit(&self, message: &str) -> Result<Option<git2::Oid>> {
+        self.commit_pathspecs(&["*".to_string()], |_| message.to_string())
+    }
+
+    /// Stage the changes to `paths`, and commit them along with anything staged before.
+    /// `message` is given the files that changed. Returns `None` if nothing changed.
+    pub fn commit_paths(
+        &self,
+        paths: &[PathBuf],
+        message: impl FnOnce(&[PathBuf]) -> String,
+    ) -> Result<Option<git2::Oid>> {
+        let pathspecs = paths
+            .iter()
+            .map(|path| {
+                let relative = path.strip_prefix(&self.path).unwrap_or(path);
+                relative.to_string_lossy().into_owned()
+            })
+            .collect::<Vec<_>>();
+        self.commit_pathspecs(&pathspecs, message)
+    }
+
+    fn commit_pathspecs(
+        &self,
+        pathspecs: &[String],
+        message: impl FnOnce(&[PathBuf]) -> String,
+    ) -> Result<Option<git2::Oid>> {
+        let repo = &self.git_repository;
+
+        let mut index = repo.index()?;
+        index.add_all(pathspecs, git2::IndexAddOption::DEFAULT, None)?;
+        // Stage deleted files too.
+        index.update_all(pathspecs, None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+
+        let parent = match repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(e.into()),
+        };
+        let parent_tree = parent.as_ref().map(|p| p.tree()).transpose()?;
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+        let changed = diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        if changed.is_empty() {
+            return Ok(None);
+        }
+
+        let signature = self.signature()?;
+        let parents = parent.iter().collect::<Vec<_>>();
+        let oid = repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            &message(&changed),
+            &tree,
+            &parents,
+        )?;
+        info!(%oid, "Committed changes");
+        Ok(Some(oid))
+    }
+
+    /// The branch, how it compares to its upstream, and which files changed.
+    pub fn git_status(&self) -> Result<GitStatus> {
+        let repo = &self.git_repository;
+        let mut status = GitStatus::default();
+
+        let head = repo.find_reference("HEAD")?;
+        status.branch = head
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+            .map(str::to_string);
+
+        // Without a remote, there is no upstream to compare with.
+        if let (Some(_), Ok(upstream)) = (&status.branch, self.upstream()) {
+            let local = repo.refname_to_id("HEAD");
+            let remote = repo.refname_to_id(&upstream.tracking_ref());
+            if let (Ok(local), Ok(remote)) = (local, remote) {
+                (status.ahead, status.behind) = repo.graph_ahead_behind(local, remote)?;
+                status.upstream = Some(format!("{}/{}", upstream.remote, upstream.remote_branch()));
+            }
+        }
+
+        status.in_progress = match repo.state() {
+            git2::RepositoryState::Clean => None,
+            git2::RepositoryState::Merge => Some("merge"),
+            git2::RepositoryState::Revert | git2::RepositoryState::RevertSequence => Some("revert"),
+            git2::RepositoryState::CherryPick | git2::RepositoryState::CherryPickSequence => {
+                Some("cherry-pick")
+            }
+            git2::RepositoryState::Bisect => Some("bisect"),
+            git2::RepositoryState::Rebase
+            | git2::RepositoryState::RebaseInteractive
+            | git2::RepositoryState::RebaseMerge => Some("rebase"),
+            git2::RepositoryState::ApplyMailbox | git2::RepositoryState::ApplyMailboxOrRebase => {
+                Some("am")
+            }
+        }
+        .map(str::to_string);
+
+        let mut options = git2::StatusOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_ignored(false);
+        for entry in repo.statuses(Some(&mut options))?.iter() {
+            let Some(path) = entry.path().map(PathBuf::from) else {
+                continue;
+            };
+            // Files in the root of the repository are not in a namespace.
+            let namespace = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .and_then(|parent| parent.components().next())
+                .map(|component| component.as_os_str().to_string_lossy().into_owned());
+            let index = match status
+                .namespaces
+                .iter()
+                .position(|ns| ns.namespace == namespace)
+            {
+                Some(index) => index,
+                None => {
+                    status.namespaces.push(NamespaceStatus {
+                        namespace,
+                        ..Default::default()
+                    });
+                    status.namespaces.len() - 1
+                }
+            };
+            let files = &mut status.namespaces[index];
+
+            let flags = entry.status();
+            if flags.is_conflicted() {
+                files.conflicted.push(path);
+                continue;
+            }
+            if flags.is_wt_new() {
+                files.untracked.push(path.clone());
+            }
+            if flags.intersects(
+                git2::Status::INDEX_NEW
+                    | git2::Status::INDEX_MODIFIED
+                    | git2::Status::INDEX_DELETED
+                    | git2::Status::INDEX_RENAMED
+                    | git2::Status::INDEX_TYPECHANGE,
+            ) {
+                files.staged.push(path.clone());
+            }
+            if flags.intersects(
+                git2::Status::WT_MODIFIED
+                    | git2::Status::WT_DELETED
+                    | git2::Status::WT_RENAMED
+                    | git2::Status::WT_TYPECHANGE,
+            ) {
+                files.modified.push(path);
+            }
+        }
+        status
+            .namespaces
+            .sort_by(|a, b| a.namespace.cmp(&b.namespace));
+
+        Ok(status)
+    }
+
+    /// The commits that changed something under `paths`, newest first.
+    /// Files that were renamed, e.g. with `fig mv`, are followed back to their old name.
+    pub fn log(&self, paths: &[PathBuf], limit: Option<usize>) -> Result<Vec<LogEntry>> {
+        let repo = &self.git_repository;
+        let mut revwalk = repo.revwalk()?;
+        match revwalk.push_head() {
+            Ok(()) => {}
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        }
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+        let mut paths = paths
+            .iter()
+            .map(|path| path.strip_prefix(&self.path).unwrap_or(path).to_path_buf())
+            .collect::<Vec<_>>();
+        let mut find_options = git2::DiffFindOptions::new();
+        find_options.renames(true);
+
+        let mut entries = vec![];
+        for oid in revwalk {
+            if limit.is_some_and(|limit| entries.len() >= limit) {
+                break;
+            }
+            let commit = repo.find_commit(oid?)?;
+            // Like git, merges are compared with the branch they were merged into.
+            let parent_tree = match commit.parents().next() {
+                Some(parent) => Some(parent.tree()?),
+                None => None,
+            };
+            let mut diff =
+                repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+            diff.find_similar(Some(&mut find_options))?;
+
+            let mut changed = paths.is_empty() && diff.deltas().len() > 0;
+            let mut renamed = vec![];
+            for delta in diff.deltas() {
+                let (Some(old), Some(new)) = (delta.old_file().path(), delta.new_file().path())
+                else {
+                    continue;
+                };
+                if !paths
+                    .iter()
+                    .any(|path| old.starts_with(path) || new.starts_with(path))
+                {
+                    continue;
+                }
+                changed = true;
+                if delta.status() == git2::Delta::Renamed
+                    && !paths.iter().any(|p| old.starts_with(p))
+                {
+                    renamed.push(old.to_path_buf());
+                }
+            }
+            paths.extend(renamed);
+            if !changed {
+                continue;
+            }
+
+            entries.push(LogEntry {
+                id: commit.id(),
+                time: UNIX_EPOCH + Duration::from_secs(commit.time().seconds().max(0) as u64),
+                author: commit.author().name().unwrap_or_default().to_string(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// The commit that `rev` refers to, e.g. `HEAD~2` or part of a commit id.
+    pub fn rev_parse(&self, rev: &str) -> Result<git2::Oid> {
+        let object = self
+            .git_repository
+            .revparse_single(rev)
+            .wrap_err(format!("Unknown revision '{rev}'"))?;
+        Ok(object.peel_to_commit()?.id())
+    }
+
+    /// The contents of `path` in the commit `id`, or of every file under it if it was a directory.
+    pub fn files_at(&self, id: git2::Oid, path: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let repo = &self.git_repository;
+        let relative = path.strip_prefix(&self.path).unwrap_or(path);
+        let tree = repo.find_commit(id)?.tree()?;
+        let Ok(entry) = tree.get_path(relative) else {
+            bail!("'{}' did not exist in {id}", relative.display());
+        };
+
+        let object = entry.to_object(repo)?;
+        if let Some(blob) = object.as_blob() {
+            return Ok(vec![(path.to_path_buf(), blob.content().to_vec())]);
+        }
+        let Some(tree) = object.as_tree() else {
+            bail!("'{}' is not a file in {id}", relative.display());
+        };
+
+        let mut files = vec![];
+        let mut error = None;
+        let walked = tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(git2::ObjectType::Blob) {
+                return git2::TreeWalkResult::Ok;
+            }
+            let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
+            match repo.find_blob(entry.id()) {
+                Ok(blob) => {
+                    files.push((path.join(dir).join(name), blob.content().to_vec()));
+                    git2::TreeWalkResult::Ok
+                }
+                Err(e) => {
+                    error = Some(e);
+                    git2::TreeWalkResult::Abort
+                }
+            }
+        });
+        // Aborting the walk is an error too, so look for the cause first.
+        if let Some(e) = error {
+            return Err(e.into());
+        }
+        walked?;
+        Ok(files)
+    }
+
+    /// Fetch the upstream of the current branch, and fast-forward to it.
+    /// If the branches have diverged, local commits are rebased on top, if `rebase` is set.
+    pub fn pull(&self, rebase: bool) -> Result<Pull> {
+        let repo = &self.git_repository;
+        let upstream = self.upstream()?;
+
+        info!("Fetching '{}' from '{}'", upstream.merge, upstream.remote);
+        let mut remote = repo.find_remote(&upstream.remote)?;
+        let mut options = git2::FetchOptions::new();
+        options.remote_callbacks(self.callbacks()?);
+        let refspec = format!("+{}:{}", upstream.merge, upstream.tracking_ref());
+        remote
+            .fetch(&[&refspec], Some(&mut options), None)
+            .wrap_err(format!("Failed to fetch from '{}'", upstream.remote))?;
+
+        // Nothing has been pushed to the remote yet.
+        let Ok(tracking) = repo.find_reference(&upstream.tracking_ref()) else {
+            return Ok(Pull::UpToDate);
+        };
+        let fetched = repo.reference_to_annotated_commit(&tracking)?;
+
+        let (analysis, _) = repo.merge_analysis(&[&fetched])?;
+        if analysis.is_up_to_date() {
+            return Ok(Pull::UpToDate);
+        }
+
+        if analysis.is_unborn() || analysis.is_fast_forward() {
+            // Check out before moving the branch, so local changes that would be
+            // overwritten stop the pull instead of being lost.
+            let target = repo.find_commit(fetched.id())?;
+            repo.checkout_tree(
+                target.as_object(),
+                Some(git2::build::CheckoutBuilder::new().safe()),
+            )
+            .wrap_err("Failed to update files, commit your changes first with `fig commit`")?;
+            let branch_ref = format!("refs/heads/{}", upstream.branch);
+            repo.reference(
+                &branch_ref,
+                fetched.id(),
+                true,
+                &format!("fig pull: fast-forward to {}", fetched.id()),
+            )?;
+            repo.set_head(&branch_ref)?;
+            return Ok(Pull::FastForward(fetched.id()));
+        }
+
+        if !rebase {
+            bail!(
+                "'{}' has diverged from '{}/{}', and can not be fast-forwarded",
+                upstream.branch,
+                upstream.remote,
+                upstream.remote_branch()
+            );
+        }
+        if !self.is_clean()? {
+            bail!("There are uncommitted changes, commit them with `fig commit` before rebasing");
+        }
+
+        let head = repo.reference_to_annotated_commit(&repo.head()?)?;
+        let signature = self.signature()?;
+        let mut git_rebase = repo.rebase(Some(&head), Some(&fetched), None, None)?;
+        let mut commits = 0;
+        while let Some(operation) = git_rebase.next() {
+            operation?;
+            if repo.index()?.has_conflicts() {
+                git_rebase.abort()?;
+                bail!(
+                    "Your commits conflict with '{}/{}', resolve it with git (`fig cmd -- git pull --rebase`)",
+                    upstream.remote,
+                    upstream.remote_branch()
+                );
+            }
+            match git_rebase.commit(None, &signature, None) {
+                Ok(_) => commits += 1,
+                // The commit is already upstream.
+                Err(e) if e.code() == git2::ErrorCode::Applied => {}
+                Err(e) => {
+                    git_rebase.abort()?;
+                    return Err(e.into());
+                }
+            }
+        }
+        git_rebase.finish(Some(&signature))?;
+
+        Ok(Pull::Rebased {
+            commits,
+            onto: fetched.id(),
+        })
+    }
+
+    /// Push the current branch to its upstream, and make it the upstream if it was not set.
     pub fn push(&self) -> Result<()> {
-        info!("Pushing repository to origin");
-        self.git_repository
-            .find_remote("origin")?
-            .push(&["master"], None)?;
+        let repo = &self.git_repository;
+        let upstream = self.upstream()?;
+        if repo.head().is_err() {
+            bail!("There are no commits to push");
+        }
+
+        info!("Pushing '{}' to '{}'", upstream.branch, upstream.remote);
+        let mut remote = repo.find_remote(&upstream.remote)?;
+        let mut rejected = None;
+        {
+            let mut callbacks = self.callbacks()?;
+            callbacks.push_update_reference(|refname, status| {
+                if let Some(status) = status {
+                    rejected = Some(format!("{refname}: {status}"));
+                }
+                Ok(())
+            });
+            let mut options = git2::PushOptions::new();
+            options.remote_callbacks(callbacks);
+            remote
+                .push(
+                    &[format!("refs/heads/{}:{}", upstream.branch, upstream.merge)],
+                    Some(&mut options),
+                )
+                .wrap_err(format!("Failed to push to '{}'", upstream.remote))?;
+        }
+        if let Some(reason) = rejected {
+            bail!("The push was rejected ({reason}), pull first with `fig pull`");
+        }
+
+        let mut config = repo.config()?;
+        config.set_str(
+            &format!("branch.{}.remote", upstream.branch),
+            &upstream.remote,
+        )?;
+        config.set_str(
+            &format!("branch.{}.merge", upstream.branch),
+            &upstream.merge,
+        )?;
         Ok(())
     }
 
-    pub fn load_plugins(&self) -> Result<PluginTriggerLookup> {
+    /// Whether there are no uncommitted changes to tracked files.
+    fn is_clean(&self) -> Result<bool> {
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(false).include_ignored(false);
+        Ok(self.git_repository.statuses(Some(&mut options))?.is_empty())
+    }
+
+    /// Who commits are made by, from git's config, or the current user if it is not set.
+    fn signature(&self) -> Result<git2::Signature<'static>> {
+        if let Ok(signature) = self.git_repository.signature() {
+            return Ok(signature);
+        }
+        let facts = Facts::get();
+        warn!("user.name and user.email are not set in git's config, using the current user");
+        Ok(git2::Signature::now(
+            &facts.username,
+            &format!("{}@{}", facts.username, facts.hostname),
+        )?)
+    }
+
+    /// Where the current branch is pulled from and pushed to.
+    /// Without an upstream, this is the branch with the same name on the only remote, or `origin`.
+    fn upstream(&self) -> Result<Upstream> {
+        let head = self.git_repository.find_reference("HEAD")?;
+        let Some(branch) = head
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+        else {
+            bail!("Not on a branch, check one out with `fig cmd -- git switch <branch>`");
+        };
+        let branch = branch.to_string();
+
+        let config = self.git_repository.config()?;
+        let remote = match config.get_string(&format!("branch.{branch}.remote")) {
+            Ok(remote) => remote,
+            Err(_) => {
+                let remotes = self.git_repository.remotes()?;
+                let remotes = remotes.iter().flatten().collect::<Vec<_>>();
+                match remotes[..] {
+                    [remote] => remote.to_string(),
+                    [] => bail!("The repository has no remote, add one with `fig cmd -- git remote add origin <url>`"),
+                    _ if remotes.contains(&"origin") => "origin".to_string(),
+                    _ => bail!("'{branch}' has no upstream, set it with `fig cmd -- git branch --set-upstream-to <remote>/<branch>`"),
+                }
+            }
+        };
+        let merge = config
+            .get_string(&format!("branch.{branch}.merge"))
+            .unwrap_or_else(|_| format!("refs/heads/{branch}"));
+
+        Ok(Upstream {
+            remote,
+            branch,
+            merge,
+        })
+    }
+
+    /// Authenticate like git does, with the ssh agent or git's credential helpers.
+    fn callbacks(&self) -> Result<git2::RemoteCallbacks<'_>> {
+        let config = self.git_repository.config()?;
+        let mut attempts = 0;
+        let mut callbacks = git2::RemoteCallbacks::new();
+        callbacks.credentials(move |url, username, allowed| {
+            // Failed credentials are asked for again, forever.
+            attempts += 1;
+            if attempts > 3 {
+                return Err(git2::Error::from_str("Authentication failed"));
+            }
+            if allowed.contains(git2::CredentialType::USERNAME) {
+                git2::Cred::username(username.unwrap_or("git"))
+            } else if allowed.contains(git2::CredentialType::SSH_KEY) {
+                git2::Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+                git2::Cred::credential_helper(&config, url, username)
+            } else {
+                git2::Cred::default()
+            }
+        });
+        Ok(callbacks)
+    }
+
+    /// Stop tracking `paths` in git, including every file in directories.
+    /// Paths are absolute, or relative to the repository.
+    pub fn remove_from_index(&self, paths: &[PathBuf]) -> Result<()> {
+        let mut index = self.git_repository.index()?;
+        for path in paths {
+            let relative = path.strip_prefix(&self.path).unwrap_or(path);
+            // Removing a directory removes everything under it, and a file is a directory
+            // without anything under it, so both are needed.
+            index.remove_path(relative)?;
+            index.remove_dir(relative, 0)?;
+        }
+        index.write()?;
+        Ok(())
+    }
+
+    /// Track the files moved from `from` to `to` in git, if `from` was tracked, like `git mv`.
+    pub fn rename_in_index(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut index = self.git_repository.index()?;
+        let from = from.strip_prefix(&self.path).unwrap_or(from);
+        let to = to.strip_prefix(&self.path).unwrap_or(to);
+
+        let tracked = index.iter().any(|entry| {
+            std::str::from_utf8(&entry.path).is_ok_and(|path| Path::new(path).starts_with(from))
+        });
+        if !tracked {
+            return Ok(());
+        }
+
+        index.remove_path(from)?;
+        index.remove_dir(from, 0)?;
+        index.add_all([to], git2::IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        Ok(())
+    }
+
+    pub fn load_plugins(&self) -> Result<PluginTriggerLookup<'_>> {
         let path = self.path().join("plugins.toml");
         if !path.exists() {
             return Ok(PluginTriggerLookup::default());
@@ -190,4 +790,165 @@ impl Repository {
 
         plugin::load_plugins(path).wrap_err("Failed to load plugins")
     }
+
+    pub fn load_settings(&self) -> Result<Settings> {
+        Settings::load(self.path()).wrap_err("Failed to load settings")
+    }
+
+    /// The variables from vars.toml, for the current machine.
+    pub fn load_vars(&self) -> Result<toml::Table> {
+        let vars = VarsFile::load(self.path()).wrap_err("Failed to load variables")?;
+        Ok(vars.resolve(Facts::get()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new repository in `dir`, with a remote at `remote`.
+    fn init(dir: &Path, remote: &Path) -> Repository {
+        let repository = RepositoryBuilder::new(dir.to_path_buf()).init().unwrap();
+        configure(&repository);
+        repository
+            .git_repository
+            .remote("origin", remote.to_str().unwrap())
+            .unwrap();
+        repository
+    }
+
+    fn clone(dir: &Path, remote: &Path) -> Repository {
+        let repository = RepositoryBuilder::new(dir.to_path_buf())
+            .clone(remote.to_str().unwrap())
+            .unwrap();
+        configure(&repository);
+        repository
+    }
+
+    fn configure(repository: &Repository) {
+        let mut config = repository.git_repository.config().unwrap();
+        config.set_str("user.name", "fig").unwrap();
+        config.set_str("user.email", "fig@example.com").unwrap();
+    }
+
+    fn write_and_commit(repository: &Repository, file: &str, contents: &str) -> git2::Oid {
+        std::fs::write(repository.path().join(file), contents).unwrap();
+        repository
+            .commit(&format!("Change {file}"))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn head(repository: &Repository) -> git2::Oid {
+        repository.rev_parse("HEAD").unwrap()
+    }
+
+    /// A bare remote, and a repository that has pushed a commit to it.
+    fn setup() -> (tempfile::TempDir, PathBuf, Repository) {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote.git");
+        git2::Repository::init_bare(&remote).unwrap();
+        let repository = init(&dir.path().join("a"), &remote);
+        write_and_commit(&repository, "file", "a\n");
+        repository.push().unwrap();
+        (dir, remote, repository)
+    }
+
+    #[test]
+    fn commit_without_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = init(&dir.path().join("a"), &dir.path().join("remote.git"));
+
+        assert!(repository.commit("First").unwrap().is_some());
+        assert_eq!(repository.commit("Again").unwrap(), None);
+    }
+
+    #[test]
+    fn push_sets_upstream() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("remote.git");
+        git2::Repository::init_bare(&remote).unwrap();
+        let repository = init(&dir.path().join("a"), &remote);
+        write_and_commit(&repository, "file", "a\n");
+
+        let branch = repository.upstream().unwrap().branch;
+        let config = repository.git_repository.config().unwrap();
+        assert!(config
+            .get_string(&format!("branch.{branch}.remote"))
+            .is_err());
+        repository.push().unwrap();
+
+        let remote = git2::Repository::open_bare(remote).unwrap();
+        let pushed = remote
+            .find_reference(&format!("refs/heads/{branch}"))
+            .unwrap();
+        assert_eq!(pushed.target(), Some(head(&repository)));
+
+        let config = repository
+            .git_repository
+            .config()
+            .unwrap()
+            .snapshot()
+            .unwrap();
+        assert_eq!(
+            config
+                .get_string(&format!("branch.{branch}.remote"))
+                .unwrap(),
+            "origin"
+        );
+    }
+
+    #[test]
+    fn pull_fast_forward() {
+        let (dir, remote, a) = setup();
+        let b = clone(&dir.path().join("b"), &remote);
+
+        let id = write_and_commit(&a, "file", "b\n");
+        a.push().unwrap();
+
+        assert!(matches!(b.pull(false).unwrap(), Pull::FastForward(oid) if oid == id));
+        assert_eq!(head(&b), id);
+        assert_eq!(
+            std::fs::read_to_string(b.path().join("file")).unwrap(),
+            "b\n"
+        );
+        assert!(matches!(b.pull(false).unwrap(), Pull::UpToDate));
+    }
+
+    #[test]
+    fn pull_diverged_without_rebase() {
+        let (dir, remote, a) = setup();
+        let b = clone(&dir.path().join("b"), &remote);
+
+        write_and_commit(&a, "file", "b\n");
+        a.push().unwrap();
+        let local = write_and_commit(&b, "other", "b\n");
+
+        let error = b.pull(false).unwrap_err();
+        assert!(error.to_string().contains("has diverged"), "{error}");
+        assert_eq!(head(&b), local);
+    }
+
+    #[test]
+    fn pull_rebase() {
+        let (dir, remote, a) = setup();
+        let b = clone(&dir.path().join("b"), &remote);
+
+        let upstream = write_and_commit(&a, "file", "b\n");
+        a.push().unwrap();
+        write_and_commit(&b, "other", "b\n");
+
+        let pull = b.pull(true).unwrap();
+        assert!(matches!(pull, Pull::Rebased { commits: 1, onto } if onto == upstream));
+        let rebased = b.git_repository.find_commit(head(&b)).unwrap();
+        assert_eq!(rebased.parent_id(0).unwrap(), upstream);
+        assert_eq!(
+            std::fs::read_to_string(b.path().join("file")).unwrap(),
+            "b\n"
+        );
+
+        // The rebased commit is on top of the upstream, so it can be pushed.
+        b.push().unwrap();
+        assert!(matches!(a.pull(false).unwrap(), Pull::FastForward(_)));
+    }
 }
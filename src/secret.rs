@@ -0,0 +1,138 @@
+use std::{
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use age::{x25519, Identity, Recipient};
+use thiserror::Error;
+
+/// Files with this extension are decrypted when they are deployed.
+pub const EXTENSION: &str = "age";
+/// The name decryption goes by, e.g. in the `disabled` plugins of a namespace.
+pub const NAME: &str = "age";
+/// Public keys that secrets are encrypted to, in the root of the repository.
+pub const RECIPIENTS_FILE: &str = "recipients.txt";
+/// The name of the identity file in fig's config directory.
+pub const IDENTITY_FILE: &str = "identity.txt";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No identity found at '{}', create one with `fig secret keygen`", .0.display())]
+    MissingIdentity(PathBuf),
+    #[error("The identity at '{}' is in the repository, where it would be committed. Move it, and set FIG_IDENTITY to where it is", .0.display())]
+    IdentityInRepository(PathBuf),
+    #[error("Failed to read identity file '{}'", .0.display())]
+    ReadIdentity(PathBuf, #[source] std::io::Error),
+    #[error("Invalid identity file '{}'", .0.display())]
+    InvalidIdentity(PathBuf, #[source] age::DecryptError),
+    #[error("Invalid recipient '{}' in {}: {}", .0, RECIPIENTS_FILE, .1)]
+    InvalidRecipient(String, &'static str),
+    #[error("Failed to encrypt file")]
+    EncryptError(#[from] age::EncryptError),
+    #[error("Failed to decrypt file: {}", .0)]
+    DecryptError(#[from] age::DecryptError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// The file with the private key of this machine, which is never stored in `repository`.
+/// Set with the FIG_IDENTITY environment variable.
+pub fn identity_path(repository: &Path) -> Result<PathBuf, Error> {
+    let path = match std::env::var_os("FIG_IDENTITY") {
+        Some(path) => PathBuf::from(path),
+        None => crate::config_dir().join(IDENTITY_FILE),
+    };
+    if crate::absolute_path(&path)?.starts_with(crate::absolute_path(repository)?) {
+        return Err(Error::IdentityInRepository(path));
+    }
+    Ok(path)
+}
+
+fn identities(repository: &Path) -> Result<Vec<Box<dyn Identity>>, Error> {
+    let path = identity_path(repository)?;
+    if !path.exists() {
+        return Err(Error::MissingIdentity(path));
+    }
+    let file = std::fs::File::open(&path).map_err(|e| Error::ReadIdentity(path.clone(), e))?;
+    age::IdentityFile::from_buffer(BufReader::new(file))
+        .map_err(|e| Error::ReadIdentity(path.clone(), e))?
+        .into_identities()
+        .map_err(|e| Error::InvalidIdentity(path, e))
+}
+
+/// Everyone a secret in `repository` is encrypted to.
+/// Without a recipients file, this is just the identity of this machine.
+fn recipients(repository: &Path) -> Result<Vec<x25519::Recipient>, Error> {
+    let path = repository.join(RECIPIENTS_FILE);
+    if !path.exists() {
+        let path = identity_path(repository)?;
+        if !path.exists() {
+            return Err(Error::MissingIdentity(path));
+        }
+        let text =
+            std::fs::read_to_string(&path).map_err(|e| Error::ReadIdentity(path.clone(), e))?;
+        return keys(&text)
+            .map(|key| {
+                x25519::Identity::from_str(key)
+                    .map(|identity| identity.to_public())
+                    .map_err(|reason| Error::InvalidRecipient("<identity>".to_string(), reason))
+            })
+            .collect();
+    }
+
+    keys(&std::fs::read_to_string(path)?)
+        .map(|key| {
+            x25519::Recipient::from_str(key)
+                .map_err(|reason| Error::InvalidRecipient(key.to_string(), reason))
+        })
+        .collect()
+}
+
+/// The keys in an identity or recipients file, one on each line, with `#` comments.
+fn keys(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+pub fn decrypt(repository: &Path, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+    let identities = identities(repository)?;
+    let decryptor = age::Decryptor::new_buffered(ciphertext)?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|i| i.as_ref()))?;
+    let mut plaintext = vec![];
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+pub fn encrypt(repository: &Path, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let recipients = recipients(repository)?;
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn Recipient))?;
+    let mut ciphertext = vec![];
+    let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+    Ok(ciphertext)
+}
+
+/// Create an identity for this machine, returning its public key.
+pub fn generate_identity(path: &Path) -> Result<x25519::Recipient, Error> {
+    use age::secrecy::ExposeSecret;
+
+    let identity = x25519::Identity::generate();
+    let recipient = identity.to_public();
+
+    if let Some(parent) = path.parent() {
+        crate::create_dir_all_if_not_exists!(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    writeln!(file, "# public key: {recipient}")?;
+    writeln!(file, "{}", identity.to_string().expose_secret())?;
+
+    Ok(recipient)
+}
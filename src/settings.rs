@@ -0,0 +1,36 @@
+use std::path::Path;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read settings.toml")]
+    ReadError(#[from] std::io::Error),
+    #[error("Failed to parse settings.toml")]
+    ParseError(#[from] toml::de::Error),
+}
+
+/// The contents of settings.toml, which changes how fig behaves for everyone using the repository.
+/// ```toml
+/// auto_commit = true
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Settings {
+    /// Commit the changes made by `add`, `capture`, `rm`, `mv`, `secret` and `namespace`.
+    #[serde(default)]
+    pub auto_commit: bool,
+}
+
+impl Settings {
+    /// Read settings.toml from the root of the repository, if it exists.
+    pub fn load(repository: &Path) -> Result<Settings, Error> {
+        let path = repository.join(SETTINGS_FILE);
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
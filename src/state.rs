@@ -0,0 +1,165 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read deployment state")]
+    ReadError(#[source] std::io::Error),
+    #[error("Failed to write deployment state")]
+    WriteError(#[source] std::io::Error),
+    #[error("Failed to parse deployment state")]
+    ParseError(#[from] serde_json::Error),
+}
+
+/// Record of what the last deploy wrote to the system.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    /// The repository the files were deployed from.
+    pub repository: PathBuf,
+    /// When the deploy happened, in seconds since the unix epoch.
+    pub deployed_at: u64,
+    pub files: Vec<DeployedFile>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeployedFile {
+    /// Where the file was deployed to.
+    pub dest: PathBuf,
+    /// The file in the repository it was deployed from.
+    pub source: PathBuf,
+    pub namespace: String,
+    /// Plugins the file was run through, in order.
+    pub plugins: Vec<String>,
+    /// Hash of the file in the repository.
+    pub source_hash: String,
+    /// Hash of the deployed file.
+    pub hash: String,
+    /// The file did not exist before fig deployed it, so fig may remove it again.
+    #[serde(default)]
+    pub created: bool,
+}
+
+/// Differences between the system, the repository and the last deploy.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Drift {
+    /// Files edited on the system since the last deploy.
+    pub modified: Vec<PathBuf>,
+    /// Files deleted from the system since the last deploy.
+    pub missing: Vec<PathBuf>,
+    /// Files edited in the repository since the last deploy.
+    pub changed: Vec<PathBuf>,
+    /// Files deleted from the repository since the last deploy.
+    pub removed: Vec<PathBuf>,
+}
+
+impl State {
+    pub fn new(repository: impl AsRef<Path>) -> State {
+        State {
+            repository: repository.as_ref().to_path_buf(),
+            deployed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            files: vec![],
+        }
+    }
+
+    pub fn path() -> PathBuf {
+        crate::state_dir().join("state.json")
+    }
+
+    /// Load the state of the last deploy from `repository`, if there was one.
+    pub fn load(repository: impl AsRef<Path>) -> Result<Option<State>, Error> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let text = std::fs::read_to_string(&path).map_err(Error::ReadError)?;
+        let state: State = serde_json::from_str(&text)?;
+
+        if !same_path(&state.repository, repository.as_ref()) {
+            warn!(
+                "Last deploy was from '{}', ignoring its state",
+                state.repository.display()
+            );
+            return Ok(None);
+        }
+
+        Ok(Some(state))
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            crate::create_dir_all_if_not_exists!(parent).map_err(Error::WriteError)?;
+        }
+
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, json).map_err(Error::WriteError)?;
+        debug!("Saved deployment state to '{}'", path.display());
+        Ok(())
+    }
+
+    pub fn get(&self, dest: impl AsRef<Path>) -> Option<&DeployedFile> {
+        self.files.iter().find(|file| file.dest == dest.as_ref())
+    }
+
+    pub fn deployed_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.deployed_at)
+    }
+
+    /// Compare the recorded hashes with the files currently on the system and in the repository.
+    pub fn drift(&self) -> std::io::Result<Drift> {
+        let mut drift = Drift::default();
+        for file in &self.files {
+            if !file.dest.exists() {
+                drift.missing.push(file.dest.clone());
+            } else if hash(&std::fs::read(&file.dest)?) != file.hash {
+                drift.modified.push(file.dest.clone());
+            }
+
+            if !file.source.exists() {
+                if !drift.removed.contains(&file.source) {
+                    drift.removed.push(file.source.clone());
+                }
+            } else if hash(&std::fs::read(&file.source)?) != file.source_hash
+                && !drift.changed.contains(&file.source)
+            {
+                drift.changed.push(file.source.clone());
+            }
+        }
+        Ok(drift)
+    }
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty()
+            && self.missing.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+/// Hex encoded sha256 hash of `bytes`.
+pub fn hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -2,57 +2,47 @@ use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 
+use crate::namespace::{NamespaceConfig, Target, CONFIG_FILE, LEGACY_CONFIG_FILE};
+
 #[derive(Error, Debug)]
 pub enum Error {
-    // If it fails to retrieve user home directory.
-    #[error("Failed to obtain base directories for your operating system")]
-    NoBaseDirs,
     #[error(transparent)]
     IoError(#[from] std::io::Error),
-    #[error("Failed to write to namespace.fig")]
-    WritingNamespaceFig(#[source] std::io::Error),
+    #[error("Failed to write to namespace.toml")]
+    WritingNamespaceConfig(#[source] std::io::Error),
+    #[error("Failed to serialize namespace")]
+    SerializingNamespace(#[from] toml::ser::Error),
 }
 
+/// The default namespaces, and where they are deployed to.
+/// The targets are expanded on each system, so they can be committed.
+const NAMESPACES: [(&str, &str); 5] = [
+    ("home", "{{home_dir}}"),
+    ("config", "{{config_dir}}"),
+    ("data", "{{data_dir}}"),
+    ("data-local", "{{data_local_dir}}"),
+    ("preferences", "{{preference_dir}}"),
+];
+
 pub fn generate(path: impl AsRef<Path>) -> Result<PathBuf, Error> {
     let path = path.as_ref().to_path_buf();
 
-    let base_dirs = directories::BaseDirs::new().ok_or(Error::NoBaseDirs)?;
-
-    // /home
-    crate::create_dir_all_if_not_exists!(path.join("home"))?;
-    std::fs::write(
-        path.join("home").join("namespace.fig"),
-        base_dirs.home_dir().display().to_string(),
-    )
-    .map_err(Error::WritingNamespaceFig)?;
-    // /config
-    crate::create_dir_all_if_not_exists!(path.join("config"))?;
-    std::fs::write(
-        path.join("config").join("namespace.fig"),
-        base_dirs.config_dir().display().to_string(),
-    )
-    .map_err(Error::WritingNamespaceFig)?;
-    // /data
-    crate::create_dir_all_if_not_exists!(path.join("data"))?;
-    std::fs::write(
-        path.join("data").join("namespace.fig"),
-        base_dirs.data_dir().display().to_string(),
-    )
-    .map_err(Error::WritingNamespaceFig)?;
-    // /data-local
-    crate::create_dir_all_if_not_exists!(path.join("data-local"))?;
-    std::fs::write(
-        path.join("data-local").join("namespace.fig"),
-        base_dirs.data_local_dir().display().to_string(),
-    )
-    .map_err(Error::WritingNamespaceFig)?;
-    // /preferences
-    crate::create_dir_all_if_not_exists!(path.join("preferences"))?;
-    std::fs::write(
-        path.join("preferences").join("namespace.fig"),
-        base_dirs.preference_dir().display().to_string(),
-    )
-    .map_err(Error::WritingNamespaceFig)?;
+    for (name, target) in NAMESPACES {
+        let dir = path.join(name);
+        crate::create_dir_all_if_not_exists!(&dir)?;
+
+        // Keep namespaces that are already defined, e.g. in a cloned repository.
+        if dir.join(CONFIG_FILE).exists() || dir.join(LEGACY_CONFIG_FILE).exists() {
+            continue;
+        }
+
+        let config = NamespaceConfig {
+            targets: vec![Target::Path(PathBuf::from(target))],
+            ..Default::default()
+        };
+        std::fs::write(dir.join(CONFIG_FILE), toml::to_string_pretty(&config)?)
+            .map_err(Error::WritingNamespaceConfig)?;
+    }
 
     Ok(path)
 }
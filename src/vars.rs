@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::facts::Facts;
+
+pub const VARS_FILE: &str = "vars.toml";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read vars.toml")]
+    ReadError(#[from] std::io::Error),
+    #[error("Failed to parse vars.toml")]
+    ParseError(#[from] toml::de::Error),
+}
+
+/// The contents of vars.toml, variables that templates can use.
+/// ```toml
+/// [vars]
+/// email = "me@example.com"
+///
+/// [hosts.work-laptop]
+/// email = "me@work.com"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct VarsFile {
+    #[serde(default)]
+    pub vars: toml::Table,
+    /// Variables that replace those in `vars`, on the machine with the same hostname.
+    #[serde(default)]
+    pub hosts: toml::Table,
+}
+
+impl VarsFile {
+    /// Read vars.toml from the root of the repository, if it exists.
+    pub fn load(repository: &Path) -> Result<VarsFile, Error> {
+        let path = repository.join(VARS_FILE);
+        if !path.exists() {
+            return Ok(VarsFile::default());
+        }
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// The variables for the current machine, with its overrides applied.
+    pub fn resolve(&self, facts: &Facts) -> toml::Table {
+        let mut vars = self.vars.clone();
+        for (hostname, overrides) in &self.hosts {
+            if !hostname.eq_ignore_ascii_case(&facts.hostname) {
+                continue;
+            }
+            if let toml::Value::Table(overrides) = overrides {
+                merge(&mut vars, overrides);
+            }
+        }
+        vars
+    }
+}
+
+/// Merge `overrides` into `vars`, keeping the keys of tables that are only in `vars`.
+fn merge(vars: &mut toml::Table, overrides: &toml::Table) {
+    for (key, value) in overrides {
+        match (vars.get_mut(key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(overrides)) => {
+                merge(table, overrides)
+            }
+            _ => {
+                vars.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts(hostname: &str) -> Facts {
+        Facts {
+            os: "linux".to_string(),
+            family: "unix".to_string(),
+            arch: "x86_64".to_string(),
+            hostname: hostname.to_string(),
+            username: "me".to_string(),
+        }
+    }
+
+    const VARS: &str = r#"
+[vars]
+editor = "vim"
+git = { name = "Me", email = "me@example.com" }
+
+[hosts.work-laptop]
+git = { email = "me@work.com" }
+"#;
+
+    #[test]
+    fn resolve_other_host() {
+        let file: VarsFile = toml::from_str(VARS).unwrap();
+        assert_eq!(file.resolve(&facts("home")), file.vars);
+    }
+
+    #[test]
+    fn resolve_nested_override() {
+        let file: VarsFile = toml::from_str(VARS).unwrap();
+        let vars = file.resolve(&facts("WORK-LAPTOP"));
+
+        assert_eq!(vars["editor"].as_str(), Some("vim"));
+        assert_eq!(vars["git"]["name"].as_str(), Some("Me"));
+        assert_eq!(vars["git"]["email"].as_str(), Some("me@work.com"));
+    }
+
+    #[test]
+    fn merge_replaces_values_that_are_not_tables() {
+        let mut vars: toml::Table = toml::from_str("a = { b = 1 }\nc = 1").unwrap();
+        let overrides: toml::Table = toml::from_str("a = 2\nc = { d = 3 }").unwrap();
+        merge(&mut vars, &overrides);
+        assert_eq!(vars, overrides);
+    }
+}